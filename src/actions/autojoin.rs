@@ -1,4 +1,271 @@
 //! Autojoin: used to op certain people in certain channels
 //!
-//! This is to be implemented later
+//! Keeps a list of users that get opped or voiced when they join a channel. A user is matched on
+//! their nickname, combined with either a hostmask (`user@host`, `*` and `?` are wildcards) or the
+//! NickServ account they are logged in with. Matching on the nickname alone is never enough.
 //!
+//! Channel ops can manage the list with:
+//! - `!autoop add <nick> <hostmask or account> [op|voice]`
+//! - `!autoop remove <nick>`
+//! - `!autoop list`
+
+use super::{Action, CommandInfo, Invocation};
+use crate::{
+    data::{Client, Join},
+    error::Error,
+};
+use futures::future::BoxFuture;
+use irc::client::prelude::ChannelMode;
+use parking_lot::RwLock;
+use tracing::error;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AutoOp {
+    server: String,
+    channel: String,
+    nickname: String,
+    #[serde(default)]
+    hostmask: Option<String>,
+    #[serde(default)]
+    account: Option<String>,
+    mode: AutoMode,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum AutoMode {
+    Op,
+    Voice,
+}
+
+impl AutoMode {
+    fn channel_mode(self) -> ChannelMode {
+        match self {
+            AutoMode::Op => ChannelMode::Oper,
+            AutoMode::Voice => ChannelMode::Voice,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            AutoMode::Op => "op",
+            AutoMode::Voice => "voice",
+        }
+    }
+}
+
+pub struct Autojoin;
+
+impl Action for Autojoin {
+    fn name(&self) -> &'static str {
+        "autojoin"
+    }
+
+    fn on_start(&self, _client: &Client) -> Result<(), Error> {
+        start();
+        Ok(())
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        &[CommandInfo {
+            name: "autoop",
+            aliases: &[],
+            help: "Manages who gets opped or voiced when joining this channel. Usage: !autoop add <nick> <hostmask or account> [op|voice], !autoop remove <nick>, !autoop list",
+        }]
+    }
+
+    fn on_command<'a>(
+        &'a self,
+        invocation: &'a Invocation<'a>,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(on_command(invocation))
+    }
+
+    fn on_join(&self, join: &Join) {
+        on_join(join);
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref AUTO_OPS: RwLock<Vec<AutoOp>> = RwLock::new(Vec::new());
+}
+
+const FILE: &str = "persist/autojoin.json";
+
+fn start() {
+    if AUTO_OPS.read().is_empty() {
+        match crate::persist::load(FILE) {
+            Ok(auto_ops) => *AUTO_OPS.write() = auto_ops,
+            Err(e) => e.report("Could not load the auto ops, starting without them"),
+        }
+    }
+}
+
+fn on_join(join: &Join) {
+    let server = join.client.server_config().host;
+    let user_host = format!("{}@{}", join.username, join.hostname);
+    let auto_ops = AUTO_OPS.read();
+
+    let matching = auto_ops.iter().find(|auto_op| {
+        auto_op.server == server
+            && auto_op.channel.eq_ignore_ascii_case(join.channel)
+            && auto_op.nickname.eq_ignore_ascii_case(join.nickname)
+            && (auto_op
+                .hostmask
+                .as_ref()
+                .map(|mask| mask_matches(mask, &user_host))
+                .unwrap_or(false)
+                || match (&auto_op.account, join.account) {
+                    (Some(expected), Some(account)) => expected.eq_ignore_ascii_case(account),
+                    _ => false,
+                })
+    });
+
+    if let Some(auto_op) = matching {
+        join.client
+            .set_user_mode(join.channel, join.nickname, auto_op.mode.channel_mode());
+    }
+}
+
+async fn on_command<'a>(invocation: &'a Invocation<'a>) -> Result<(), Error> {
+    let channel = match invocation.channel.as_ref() {
+        Some(channel) if invocation.sender_is_op() => channel.name(),
+        _ => return Ok(()),
+    };
+    let server = &invocation.server_config.host;
+
+    let mut args = invocation.args.iter().map(String::as_str);
+    match (args.next(), args.next(), args.next(), args.next()) {
+        (Some("add"), Some(nickname), Some(mask), mode) => {
+            let mode = match mode {
+                None | Some("op") => AutoMode::Op,
+                Some("voice") => AutoMode::Voice,
+                Some(_) => {
+                    invocation.reply("Usage: !autoop add <nick> <hostmask or account> [op|voice]");
+                    return Ok(());
+                }
+            };
+            let (hostmask, account) = if mask.contains('@') {
+                (Some(mask.to_owned()), None)
+            } else {
+                (None, Some(mask.to_owned()))
+            };
+
+            let mut auto_ops = AUTO_OPS.write();
+            auto_ops.retain(|a| {
+                !(a.server == *server
+                    && a.channel.eq_ignore_ascii_case(&channel)
+                    && a.nickname.eq_ignore_ascii_case(nickname))
+            });
+            auto_ops.push(AutoOp {
+                server: server.clone(),
+                channel: channel.clone(),
+                nickname: nickname.to_owned(),
+                hostmask,
+                account,
+                mode,
+            });
+            if let Err(e) = crate::persist::save(FILE, &*auto_ops) {
+                error!(error = %e, "Could not save the auto-op list");
+                invocation.reply("Could not save the auto-op list");
+                return Ok(());
+            }
+            invocation.reply(format!(
+                "Will auto-{} {} in {}",
+                mode.name(),
+                nickname,
+                channel
+            ));
+        }
+        (Some("remove"), Some(nickname), None, None) => {
+            let mut auto_ops = AUTO_OPS.write();
+            let count = auto_ops.len();
+            auto_ops.retain(|a| {
+                !(a.server == *server
+                    && a.channel.eq_ignore_ascii_case(&channel)
+                    && a.nickname.eq_ignore_ascii_case(nickname))
+            });
+            if auto_ops.len() == count {
+                invocation.reply(format!("{} is not on the auto-op list", nickname));
+            } else {
+                if let Err(e) = crate::persist::save(FILE, &*auto_ops) {
+                    error!(error = %e, "Could not save the auto-op list");
+                    invocation.reply("Could not save the auto-op list");
+                    return Ok(());
+                }
+                invocation.reply(format!("Removed {} from the auto-op list", nickname));
+            }
+        }
+        (Some("list"), None, None, None) => {
+            let auto_ops = AUTO_OPS.read();
+            let list = auto_ops
+                .iter()
+                .filter(|a| a.server == *server && a.channel.eq_ignore_ascii_case(&channel))
+                .map(|a| {
+                    format!(
+                        "{} ({} {})",
+                        a.nickname,
+                        a.mode.name(),
+                        a.hostmask
+                            .as_deref()
+                            .or(a.account.as_deref())
+                            .unwrap_or_default()
+                    )
+                })
+                .collect::<Vec<_>>();
+            if list.is_empty() {
+                invocation.reply(format!("Nobody is auto-opped in {}", channel));
+            } else {
+                invocation.reply(format!("Auto-op list: {}", list.join(", ")));
+            }
+        }
+        _ => {
+            invocation.reply("Usage: !autoop add <nick> <hostmask or account> [op|voice], !autoop remove <nick>, !autoop list");
+        }
+    }
+    Ok(())
+}
+
+/// Case-insensitive glob match, where `*` matches any amount of characters and `?` matches
+/// exactly one.
+fn mask_matches(mask: &str, value: &str) -> bool {
+    let mask = mask.to_lowercase().chars().collect::<Vec<_>>();
+    let value = value.to_lowercase().chars().collect::<Vec<_>>();
+
+    let (mut m, mut v) = (0, 0);
+    let mut backtrack = None;
+    while v < value.len() {
+        match mask.get(m) {
+            Some('*') => {
+                backtrack = Some((m, v));
+                m += 1;
+            }
+            Some(&c) if c == '?' || c == value[v] => {
+                m += 1;
+                v += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    m = star + 1;
+                    v = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    mask[m..].iter().all(|c| *c == '*')
+}
+
+#[test]
+fn test_mask_matches() {
+    assert!(mask_matches(
+        "trangar@*.example.com",
+        "Trangar@irc.example.com"
+    ));
+    assert!(mask_matches("*@host", "anyone@host"));
+    assert!(mask_matches("tr?ngar@host", "trangar@host"));
+    assert!(mask_matches("*", "x@y"));
+    assert!(!mask_matches("trangar@host", "trangar@host.evil"));
+    assert!(!mask_matches("*@host", "anyone@otherhost"));
+}
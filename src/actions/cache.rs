@@ -0,0 +1,74 @@
+//! Cache
+//!
+//! Remembers the result of a request for a short while, so that users asking for the same thing
+//! over and over don't hit the website every time.
+
+use crate::error::Error;
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    future::Future,
+    time::{Duration, Instant},
+};
+
+pub struct Cache<T> {
+    lifetime: Duration,
+    /// The moment each value was stored
+    entries: Mutex<HashMap<String, (Instant, T)>>,
+}
+
+impl<T: Clone> Cache<T> {
+    pub fn new(lifetime: Duration) -> Self {
+        Self {
+            lifetime,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<T> {
+        let mut entries = self.entries.lock();
+        let now = Instant::now();
+        entries.retain(|_, (stored, _)| now.duration_since(*stored) < self.lifetime);
+        entries.get(key).map(|(_, value)| value.clone())
+    }
+
+    pub fn insert(&self, key: &str, value: T) {
+        self.entries
+            .lock()
+            .insert(key.to_owned(), (Instant::now(), value));
+    }
+
+    /// Return the cached value for `key`, or call `fetch` and cache its result if it succeeds
+    pub async fn get_or_fetch<F, Fut>(&self, key: &str, fetch: F) -> Result<T, Error>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        if let Some(value) = self.get(key) {
+            return Ok(value);
+        }
+        let value = fetch().await?;
+        self.insert(key, value.clone());
+        Ok(value)
+    }
+}
+
+#[tokio::test]
+async fn test_cache() {
+    let cache = Cache::new(Duration::from_secs(60));
+    assert_eq!(cache.get_or_fetch("a", || async { Ok(1) }).await, Ok(1));
+    assert_eq!(cache.get_or_fetch("a", || async { Ok(2) }).await, Ok(1));
+    assert_eq!(
+        cache
+            .get_or_fetch("b", || async {
+                Err(Error::NotFound(String::from("error")))
+            })
+            .await,
+        Err(Error::NotFound(String::from("error")))
+    );
+    assert_eq!(cache.get("b"), None);
+
+    let cache = Cache::new(Duration::ZERO);
+    cache.insert("a", 1);
+    assert_eq!(cache.get("a"), None);
+}
@@ -1,137 +1,797 @@
-use crate::data::Message;
+use super::{
+    cooldown,
+    feed_watcher::{self, feed_commands},
+    invocation::tokenize,
+    template, Action, CommandInfo, Invocation,
+};
+use crate::{data::Client, error::Error, http};
+use futures::future::BoxFuture;
 use parking_lot::RwLock;
 use std::{
-    io::Write as _,
-    time::{Duration, Instant},
+    collections::HashMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
+use tracing::error;
 
 #[derive(Serialize, Deserialize)]
 pub struct Command {
     trigger: String,
     response: Vec<String>,
-    #[serde(skip)]
-    last_invoke: LastInvoke,
+    /// Where this command can be used
+    #[serde(default, skip_serializing_if = "Scope::is_global")]
+    scope: Scope,
+    /// How many seconds a channel has to wait before this command can be used again. Defaults to
+    /// `COMMAND_TIMEOUT_SECONDS`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cooldown: Option<u64>,
+    /// How many seconds a single user has to wait before they can use this command again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    user_cooldown: Option<u64>,
+    /// Every change made to this command, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    history: Vec<Revision>,
+}
+
+impl Command {
+    /// Forgotten commands are kept with an empty response, so that `!rollback` can bring them back
+    fn is_forgotten(&self) -> bool {
+        self.response.is_empty()
+    }
+
+    /// Change the response of this command, keeping track of who changed it
+    fn change(&mut self, author: Author, change: Change, response: Vec<String>) {
+        let previous_response = std::mem::replace(&mut self.response, response);
+        self.history.push(Revision {
+            author,
+            timestamp: unix_timestamp(),
+            change,
+            previous_response,
+        });
+    }
+
+    fn rename(&mut self, author: Author, trigger: String) {
+        let previous_trigger = std::mem::replace(&mut self.trigger, trigger);
+        let response = self.response.clone();
+        self.change(author, Change::Rename { previous_trigger }, response);
+    }
+
+    /// Restore the response from before revision number `revision`, or explain why that is not
+    /// possible
+    fn rollback(&mut self, author: Author, revision: usize) -> Result<(), String> {
+        let response = match revision
+            .checked_sub(1)
+            .and_then(|index| self.history.get(index))
+        {
+            Some(Revision {
+                change: Change::Rename { previous_trigger },
+                ..
+            }) => {
+                return Err(format!(
+                    "Revision #{} renamed {:?} from {:?}, use !rename to undo it",
+                    revision, self.trigger, previous_trigger
+                ))
+            }
+            Some(revision) => revision.previous_response.clone(),
+            None => return Err(format!("{:?} has no revision #{}", self.trigger, revision)),
+        };
+        if response.is_empty() {
+            return Err(format!(
+                "Revision #{} created {:?}, use !forget to remove it",
+                revision, self.trigger
+            ));
+        }
+        self.change(author, Change::Edit, response);
+        Ok(())
+    }
+
+    /// One line per revision, newest first
+    fn describe_history(&self, now: u64) -> Vec<String> {
+        self.history
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(index, revision)| {
+                let what = match &revision.change {
+                    Change::Rename { previous_trigger } => {
+                        format!("renamed from {:?}", previous_trigger)
+                    }
+                    Change::Edit if revision.previous_response.is_empty() => {
+                        String::from("created")
+                    }
+                    Change::Edit => String::from("changed"),
+                    Change::Forget => String::from("forgotten"),
+                };
+                let mut line = format!(
+                    "#{} {} by {} in {} on {} {}",
+                    index + 1,
+                    what,
+                    revision.author.nick,
+                    revision.author.channel,
+                    revision.author.server,
+                    format_age(now.saturating_sub(revision.timestamp))
+                );
+                if !revision.previous_response.is_empty()
+                    && !matches!(revision.change, Change::Rename { .. })
+                {
+                    let previous = revision.previous_response.join(" | ");
+                    line += &format!(", was {:?}", http::shorten(&previous, MAX_PREVIOUS_LENGTH));
+                }
+                line
+            })
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Revision {
+    #[serde(flatten)]
+    author: Author,
+    /// Seconds since the unix epoch
+    timestamp: u64,
+    #[serde(default, skip_serializing_if = "Change::is_edit")]
+    change: Change,
+    /// The response before this change was made. Empty if the command was created by this change.
+    previous_response: Vec<String>,
+}
+
+/// Who made a change, and where
+#[derive(Serialize, Deserialize, Clone)]
+struct Author {
+    #[serde(rename = "author")]
+    nick: String,
+    channel: String,
+    server: String,
+}
+
+impl Author {
+    fn of(invocation: &Invocation) -> Author {
+        Author {
+            nick: invocation.sender.to_owned(),
+            channel: invocation.reply_to.to_owned(),
+            server: invocation.server_config.host.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum Change {
+    /// The command was created, or its response changed
+    #[default]
+    Edit,
+    /// The command was renamed, its response stayed the same
+    Rename { previous_trigger: String },
+    /// The command was removed with `!forget`
+    Forget,
+}
+
+impl Change {
+    fn is_edit(&self) -> bool {
+        *self == Change::Edit
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+enum Scope {
+    #[default]
+    Global,
+    Server {
+        server: String,
+    },
+    Channel {
+        server: String,
+        channel: String,
+    },
+}
+
+impl Scope {
+    /// The most specific scope of the place where the command was invoked
+    fn of(invocation: &Invocation) -> Scope {
+        let server = invocation.server_config.host.clone();
+        if invocation.channel.is_some() {
+            Scope::Channel {
+                server,
+                channel: invocation.reply_to.to_lowercase(),
+            }
+        } else {
+            Scope::Server { server }
+        }
+    }
+
+    fn is_global(&self) -> bool {
+        *self == Scope::Global
+    }
+
+    /// How specific this scope is for the place where the command was invoked, or `None` if a
+    /// command in this scope can not be used there
+    fn specificity(&self, invocation: &Invocation) -> Option<u8> {
+        match self {
+            Scope::Global => Some(0),
+            Scope::Server { server } if *server == invocation.server_config.host => Some(1),
+            Scope::Channel { server, channel }
+                if *server == invocation.server_config.host
+                    && invocation.channel.is_some()
+                    && channel.eq_ignore_ascii_case(invocation.reply_to) =>
+            {
+                Some(2)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// All learned commands, indexed by their trigger. A trigger can have a different command in
+/// every scope.
+#[derive(Default)]
+struct CommandMap(HashMap<String, Vec<Command>>);
+
+impl CommandMap {
+    fn from_list(commands: Vec<Command>) -> Self {
+        let mut map = CommandMap::default();
+        for command in commands {
+            map.insert(command);
+        }
+        map
+    }
+
+    /// All commands, sorted by trigger and scope
+    fn to_list(&self) -> Vec<&Command> {
+        let mut commands = self.0.values().flatten().collect::<Vec<_>>();
+        commands.sort_unstable_by(|a, b| (&a.trigger, &a.scope).cmp(&(&b.trigger, &b.scope)));
+        commands
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The index of the command with this trigger in the most specific scope that is visible to
+    /// `invocation`. Forgotten commands are skipped unless `forgotten` is set.
+    fn index_of(&self, trigger: &str, invocation: &Invocation, forgotten: bool) -> Option<usize> {
+        let commands = self.0.get(trigger)?;
+        (0..commands.len())
+            .filter(|index| forgotten || !commands[*index].is_forgotten())
+            .filter_map(|index| Some((index, commands[index].scope.specificity(invocation)?)))
+            .max_by_key(|(_, specificity)| *specificity)
+            .map(|(index, _)| index)
+    }
+
+    /// The command with this trigger in the most specific scope that is visible to `invocation`
+    fn get(&self, trigger: &str, invocation: &Invocation) -> Option<&Command> {
+        let index = self.index_of(trigger, invocation, false)?;
+        Some(&self.0[trigger][index])
+    }
+
+    fn get_mut(&mut self, trigger: &str, invocation: &Invocation) -> Option<&mut Command> {
+        let index = self.index_of(trigger, invocation, false)?;
+        self.0.get_mut(trigger).map(|commands| &mut commands[index])
+    }
+
+    /// Like `get`, but forgotten commands are included, so their history can be seen
+    fn get_with_forgotten(&self, trigger: &str, invocation: &Invocation) -> Option<&Command> {
+        let index = self.index_of(trigger, invocation, true)?;
+        Some(&self.0[trigger][index])
+    }
+
+    fn get_with_forgotten_mut(
+        &mut self,
+        trigger: &str,
+        invocation: &Invocation,
+    ) -> Option<&mut Command> {
+        let index = self.index_of(trigger, invocation, true)?;
+        self.0.get_mut(trigger).map(|commands| &mut commands[index])
+    }
+
+    fn get_in_scope_mut(&mut self, trigger: &str, scope: &Scope) -> Option<&mut Command> {
+        self.0
+            .get_mut(trigger)?
+            .iter_mut()
+            .find(|command| command.scope == *scope)
+    }
+
+    /// Insert the command, replacing the command with the same trigger and scope
+    fn insert(&mut self, command: Command) {
+        let commands = self.0.entry(command.trigger.to_lowercase()).or_default();
+        commands.retain(|c| c.scope != command.scope);
+        commands.push(command);
+    }
+
+    /// Remove the command with this trigger in the most specific scope that is visible to
+    /// `invocation`
+    fn remove(&mut self, trigger: &str, invocation: &Invocation) -> Option<Command> {
+        let index = self.index_of(trigger, invocation, false)?;
+        let commands = self.0.get_mut(trigger)?;
+        let command = commands.remove(index);
+        if commands.is_empty() {
+            self.0.remove(trigger);
+        }
+        Some(command)
+    }
+
+    /// The triggers of all commands that can be used where `invocation` was sent
+    fn visible_triggers(&self, invocation: &Invocation) -> Vec<&str> {
+        let mut triggers = self
+            .0
+            .iter()
+            .filter(|(_, commands)| {
+                commands
+                    .iter()
+                    .any(|c| !c.is_forgotten() && c.scope.specificity(invocation).is_some())
+            })
+            .map(|(trigger, _)| trigger.as_str())
+            .collect::<Vec<_>>();
+        triggers.sort_unstable();
+        triggers
+    }
 }
 
 const COMMAND_TIMEOUT_SECONDS: u64 = 60;
 
+/// How much of an old response `!history` shows
+const MAX_PREVIOUS_LENGTH: usize = 60;
+
 lazy_static::lazy_static! {
-    static ref COMMANDS: RwLock<Vec<Command>> = RwLock::new(Vec::new());
-    static ref LAST_HELP_COMMAND: RwLock<LastInvoke> = RwLock::new(LastInvoke::default());
+    static ref COMMANDS: RwLock<CommandMap> = RwLock::new(CommandMap::default());
 }
 
 const FILE: &str = "persist/commands.json";
 
-pub fn start() {
+fn start() {
     if COMMANDS.read().is_empty() {
         if let Ok(file) = std::fs::File::open(FILE) {
             let commands: Vec<Command> =
                 serde_json::from_reader(file).expect("Could not load commands.json");
-            *COMMANDS.write() = commands;
+            *COMMANDS.write() = CommandMap::from_list(commands);
         }
     }
 }
 
-#[derive(Default)]
-struct LastInvoke(Option<Instant>);
-impl LastInvoke {
-    pub fn can_invoke_again(&self) -> bool {
-        let command_timeout = Duration::from_secs(COMMAND_TIMEOUT_SECONDS);
+pub struct Commands;
 
-        if let Some(instant) = &self.0 {
-            instant.elapsed() > command_timeout
-        } else {
-            true
-        }
+pub const NAME: &str = "commands";
+
+impl Action for Commands {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn on_start(&self, _client: &Client) -> Result<(), Error> {
+        start();
+        Ok(())
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        &[
+            CommandInfo {
+                name: "learn",
+                aliases: &[],
+                help: "Teaches the bot a command, only for this channel unless --server or --global is given. Usage: !learn [--global|--server] <trigger> = <text>",
+            },
+            CommandInfo {
+                name: "forget",
+                aliases: &[],
+                help: "Removes a learned command, !rollback brings it back. Usage: !forget <trigger>",
+            },
+            CommandInfo {
+                name: "rename",
+                aliases: &[],
+                help: "Renames a learned command. Usage: !rename <old trigger> <new trigger>",
+            },
+            CommandInfo {
+                name: "append",
+                aliases: &[],
+                help: "Adds a line to the response of a learned command. Usage: !append <trigger> = <text>",
+            },
+            CommandInfo {
+                name: "history",
+                aliases: &[],
+                help: "Shows who changed a learned command, when, and what it said before. Usage: !history <trigger>",
+            },
+            CommandInfo {
+                name: "rollback",
+                aliases: &["undo"],
+                help: "Undoes changes to a learned command, the latest one by default. Usage: !rollback <trigger> [revision number]",
+            },
+        ]
     }
 
-    pub fn has_invoked(&mut self) {
-        self.0 = Some(Instant::now());
+    fn handles_unknown_commands(&self) -> bool {
+        true
+    }
+
+    fn on_command<'a>(
+        &'a self,
+        invocation: &'a Invocation<'a>,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(on_command(invocation))
     }
 }
 
-pub async fn on_message<'a>(message: &'a Message<'a>) -> Result<(), String> {
-    if message.body.trim() == "!help" {
-        if LAST_HELP_COMMAND.read().can_invoke_again() {
-            let commands = COMMANDS.read();
+async fn on_command<'a>(invocation: &'a Invocation<'a>) -> Result<(), Error> {
+    match invocation.command.as_str() {
+        "learn" if invocation.sender_is_op() => learn(invocation),
+        "forget" if invocation.sender_is_op() => forget(invocation),
+        "rename" if invocation.sender_is_op() => rename(invocation),
+        "append" if invocation.sender_is_op() => append(invocation),
+        "history" if invocation.sender_is_op() => history(invocation),
+        "rollback" if invocation.sender_is_op() => rollback(invocation),
+        "learn" | "forget" | "rename" | "append" | "history" | "rollback" => {}
+        _ => run_learned_command(invocation),
+    }
+    Ok(())
+}
 
-            let commands = commands
-                .iter()
-                .map(|c| c.trigger.as_str())
-                .collect::<Vec<_>>();
+/// The triggers of all learned commands that can be used where `invocation` was sent
+pub fn visible_triggers(invocation: &Invocation) -> Vec<String> {
+    COMMANDS
+        .read()
+        .visible_triggers(invocation)
+        .into_iter()
+        .map(String::from)
+        .collect()
+}
 
-            message.reply(format!(
-                "Commands: {} (All commands have a 1 minute cooldown)",
-                commands.join(", ")
-            ));
+/// Split `trigger = text` in its trimmed trigger and text
+fn split_assignment(text: &str) -> Option<(String, String)> {
+    let (trigger, text) = text.split_once('=')?;
+    let trigger = trigger.trim().to_lowercase();
+    let text = text.trim().to_owned();
+    if trigger.is_empty() || text.is_empty() {
+        None
+    } else {
+        Some((trigger, text))
+    }
+}
+
+/// Whether a feed on this server already answers to the first word of `trigger`. Feed commands
+/// win over learned commands, so a learned command with such a trigger would never be used.
+fn is_feed_command(invocation: &Invocation, trigger: &str) -> bool {
+    let word = trigger.split_whitespace().next().unwrap_or_default();
+    feed_commands(invocation.server_config).any(|feed| feed.command.as_deref() == Some(word))
+}
 
-            LAST_HELP_COMMAND.write().has_invoked();
+/// `!learn [--global|--server] <trigger> = <text>`
+///
+/// Commands are only available in the channel they are learned in, unless `--server` or
+/// `--global` is given.
+fn learn(invocation: &Invocation) {
+    let (flag, rest) = invocation
+        .raw_args
+        .split_once(char::is_whitespace)
+        .unwrap_or((invocation.raw_args, ""));
+    let (scope, assignment) = match flag {
+        "--global" => (Scope::Global, rest),
+        "--server" => {
+            let server = invocation.server_config.host.clone();
+            (Scope::Server { server }, rest)
         }
-        return Ok(());
+        _ => (Scope::of(invocation), invocation.raw_args),
+    };
+    let (trigger, response) = match split_assignment(assignment) {
+        Some(assignment) => assignment,
+        None => {
+            invocation.reply("Usage: !learn [--global|--server] <trigger> = <text>");
+            return;
+        }
+    };
+    if is_feed_command(invocation, &trigger) {
+        invocation.reply(format!("{:?} is already a feed command", trigger));
+        return;
+    }
+    let mut commands = COMMANDS.write();
+    if commands.get_in_scope_mut(&trigger, &scope).is_none() {
+        commands.insert(Command {
+            trigger: trigger.clone(),
+            response: Vec::new(),
+            scope: scope.clone(),
+            cooldown: None,
+            user_cooldown: None,
+            history: Vec::new(),
+        });
     }
+    if let Some(command) = commands.get_in_scope_mut(&trigger, &scope) {
+        command.change(Author::of(invocation), Change::Edit, vec![response]);
+    }
+    save(invocation, &commands, "Command saved");
+}
 
-    if message.body.trim().starts_with("!learn")
-        && message
-            .channel
-            .as_ref()
-            .map(|c| c.user_is_op(message.sender))
-            .unwrap_or(false)
+fn forget(invocation: &Invocation) {
+    let trigger = invocation.raw_args.to_lowercase();
+    let mut commands = COMMANDS.write();
+    if let Some(command) = commands.get_mut(&trigger, invocation) {
+        command.change(Author::of(invocation), Change::Forget, Vec::new());
+        save(invocation, &commands, "Command forgotten");
+    } else {
+        invocation.reply(format!("Unknown command {:?}", trigger));
+    }
+}
+
+fn rename(invocation: &Invocation) {
+    let (old, new) = match invocation.args.as_slice() {
+        [old, new] => (old.to_lowercase(), new.to_lowercase()),
+        _ => {
+            invocation.reply("Usage: !rename <old trigger> <new trigger>");
+            return;
+        }
+    };
+    if is_feed_command(invocation, &new) {
+        invocation.reply(format!("{:?} is already a feed command", new));
+        return;
+    }
+    let mut commands = COMMANDS.write();
+    let scope = match commands.get(&old, invocation) {
+        Some(command) => command.scope.clone(),
+        None => {
+            invocation.reply(format!("Unknown command {:?}", old));
+            return;
+        }
+    };
+    if commands
+        .get_in_scope_mut(&new, &scope)
+        .is_some_and(|command| !command.is_forgotten())
     {
-        let remaining = &message.body.trim()["!learn".len()..];
-        let mut split = remaining.split('=');
-        if let Some(left_hand) = split.next() {
-            let left_hand = left_hand.trim();
-
-            let right_hand = split
-                .fold(String::new(), |s, item| {
-                    format!("{}{}{}", s, if s.is_empty() { "" } else { " " }, item)
-                })
-                .trim()
-                .to_owned();
-            if !right_hand.is_empty() {
-                let mut commands = COMMANDS.write();
-                commands.retain(|c| c.trigger != left_hand);
-                commands.push(Command {
-                    trigger: left_hand.to_owned(),
-                    response: vec![right_hand],
-                    last_invoke: LastInvoke::default(),
-                });
-                let mut f = match std::fs::File::create(FILE) {
-                    Ok(c) => c,
-                    Err(e) => {
-                        eprintln!("Could not open commands.json for writing");
-                        eprintln!("{:?}", e);
-                        return Ok(());
-                    }
-                };
-                let json = match serde_json::to_string_pretty(&*commands) {
-                    Ok(json) => json,
-                    Err(e) => {
-                        eprintln!("Could not serialize commands");
-                        eprintln!("{:?}", e);
-                        return Ok(());
-                    }
-                };
-                if let Err(e) = f.write_all(json.as_bytes()) {
-                    eprintln!("Could not write commands json to file");
-                    eprintln!("{:?}", e);
-                }
-                message.reply("Command saved");
-            }
+        invocation.reply(format!("Command {:?} already exists", new));
+        return;
+    }
+    match commands.remove(&old, invocation) {
+        Some(mut command) => {
+            command.rename(Author::of(invocation), new);
+            commands.insert(command);
+        }
+        None => {
+            invocation.reply(format!("Unknown command {:?}", old));
+            return;
         }
-        return Ok(());
     }
-    if message.body.starts_with('!') {
-        let text = &message.body[1..];
-        for command in COMMANDS.write().iter_mut() {
-            if text.starts_with(&command.trigger) && command.last_invoke.can_invoke_again() {
-                command.last_invoke.has_invoked();
-                for response in &command.response {
-                    message.reply(response);
-                }
-                return Ok(());
-            }
+    save(invocation, &commands, "Command renamed");
+}
+
+fn append(invocation: &Invocation) {
+    let (trigger, line) = match split_assignment(invocation.raw_args) {
+        Some(assignment) => assignment,
+        None => {
+            invocation.reply("Usage: !append <trigger> = <text>");
+            return;
+        }
+    };
+    let mut commands = COMMANDS.write();
+    match commands.get_mut(&trigger, invocation) {
+        Some(command) => {
+            let mut response = command.response.clone();
+            response.push(line);
+            command.change(Author::of(invocation), Change::Edit, response);
+        }
+        None => {
+            invocation.reply(format!("Unknown command {:?}", trigger));
+            return;
         }
     }
-    Ok(())
+    save(invocation, &commands, "Command saved");
+}
+
+fn history(invocation: &Invocation) {
+    let trigger = invocation.raw_args.to_lowercase();
+    let commands = COMMANDS.read();
+    let command = match commands.get_with_forgotten(&trigger, invocation) {
+        Some(command) => command,
+        None => {
+            invocation.reply(format!("Unknown command {:?}", trigger));
+            return;
+        }
+    };
+    if command.history.is_empty() {
+        invocation.reply(format!("{:?} has no recorded changes", trigger));
+        return;
+    }
+
+    let revisions = command.describe_history(unix_timestamp());
+    invocation.reply(format!(
+        "History of {:?}: {} (use !rollback {} <number> to undo a change)",
+        trigger,
+        revisions[..revisions.len().min(5)].join(", "),
+        trigger
+    ));
+}
+
+fn rollback(invocation: &Invocation) {
+    let (trigger, revision) = match invocation.args.as_slice() {
+        [trigger] => (trigger.to_lowercase(), None),
+        [trigger, revision] => match revision.trim_start_matches('#').parse::<usize>() {
+            Ok(revision) => (trigger.to_lowercase(), Some(revision)),
+            Err(_) => (String::new(), None),
+        },
+        _ => (String::new(), None),
+    };
+    if trigger.is_empty() {
+        invocation.reply("Usage: !rollback <trigger> [revision number]");
+        return;
+    }
+
+    let mut commands = COMMANDS.write();
+    let command = match commands.get_with_forgotten_mut(&trigger, invocation) {
+        Some(command) => command,
+        None => {
+            invocation.reply(format!("Unknown command {:?}", trigger));
+            return;
+        }
+    };
+    let revision = revision.unwrap_or(command.history.len());
+    match command.rollback(Author::of(invocation), revision) {
+        Ok(()) => save(invocation, &commands, "Command rolled back"),
+        Err(message) => invocation.reply(message),
+    }
+}
+
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn format_age(seconds: u64) -> String {
+    match seconds {
+        s if s < 60 => String::from("just now"),
+        s if s < 60 * 60 => format!("{} minutes ago", s / 60),
+        s if s < 60 * 60 * 24 => format!("{} hours ago", s / (60 * 60)),
+        s => format!("{} days ago", s / (60 * 60 * 24)),
+    }
+}
+
+/// Persist the commands, and let the user know if that worked
+fn save(invocation: &Invocation, commands: &CommandMap, success_message: &str) {
+    match crate::persist::save(FILE, &commands.to_list()) {
+        Ok(()) => invocation.reply(success_message),
+        Err(e) => {
+            error!(error = %e, "Could not save commands");
+            invocation.reply("Could not save commands");
+        }
+    }
+}
+
+fn run_learned_command(invocation: &Invocation) {
+    let channel = invocation.channel.as_ref().map(|_| invocation.reply_to);
+    if invocation
+        .server_config
+        .action_enabled(feed_watcher::NAME, channel)
+        && is_feed_command(invocation, &invocation.command)
+    {
+        return;
+    }
+    let text = format!("{} {}", invocation.command, invocation.raw_args);
+    let commands = COMMANDS.read();
+    let (command, args) = match find_command(&text, |trigger| commands.get(trigger, invocation)) {
+        Some(found) => found,
+        None => return,
+    };
+
+    let cooldown = Duration::from_secs(command.cooldown.unwrap_or(COMMAND_TIMEOUT_SECONDS));
+    let user_cooldown = command.user_cooldown.map(Duration::from_secs);
+    if cooldown::try_invoke(invocation, &command.trigger, cooldown, user_cooldown) {
+        let topic = invocation
+            .channel
+            .as_ref()
+            .map(|c| c.topic())
+            .unwrap_or_default();
+        let context = template::Context {
+            nick: invocation.sender,
+            args,
+            arg_list: &tokenize(args),
+            channel: invocation.reply_to,
+            topic: &topic,
+        };
+        for response in &command.response {
+            invocation.reply(template::render(response, &context));
+        }
+    }
+}
+
+/// Find the command whose trigger matches the start of `text`, ending on a word boundary. Longer
+/// triggers are preferred, so an exact match always wins. Returns the command and the text after
+/// its trigger.
+///
+/// `lookup` is called with lowercased triggers.
+fn find_command<'a, 'b>(
+    text: &'b str,
+    lookup: impl Fn(&str) -> Option<&'a Command>,
+) -> Option<(&'a Command, &'b str)> {
+    let text = text.trim();
+    let word_ends = text
+        .char_indices()
+        .filter(|(_, c)| c.is_whitespace())
+        .map(|(index, _)| index)
+        .chain(std::iter::once(text.len()))
+        .collect::<Vec<_>>();
+
+    word_ends.into_iter().rev().find_map(|end| {
+        let trigger = text[..end].trim_end().to_lowercase();
+        lookup(&trigger).map(|command| (command, text[end..].trim()))
+    })
+}
+
+#[test]
+fn test_split_assignment() {
+    assert_eq!(
+        split_assignment(" Bugs = https://forums.factorio.com/viewforum.php?f=7 "),
+        Some((
+            String::from("bugs"),
+            String::from("https://forums.factorio.com/viewforum.php?f=7")
+        ))
+    );
+    assert_eq!(split_assignment("bugs ="), None);
+    assert_eq!(split_assignment("= text"), None);
+    assert_eq!(split_assignment("bugs"), None);
+}
+
+#[test]
+fn test_find_command() {
+    let commands = ["type", "github", "foo", "foo bar"]
+        .iter()
+        .map(|trigger| {
+            let command = Command {
+                trigger: trigger.to_string(),
+                response: Vec::new(),
+                scope: Scope::Global,
+                cooldown: None,
+                user_cooldown: None,
+                history: Vec::new(),
+            };
+            (trigger.to_string(), command)
+        })
+        .collect::<HashMap<_, _>>();
+    let find = |text| {
+        find_command(text, |trigger| commands.get(trigger))
+            .map(|(c, args)| (c.trigger.as_str(), args))
+    };
+
+    assert_eq!(find("types "), None);
+    assert_eq!(find("github2"), None);
+    assert_eq!(find("type "), Some(("type", "")));
+    assert_eq!(find("GitHub please"), Some(("github", "please")));
+    assert_eq!(find("foo bar baz"), Some(("foo bar", "baz")));
+    assert_eq!(find("foo barbaz"), Some(("foo", "barbaz")));
+}
+
+#[test]
+fn test_history_and_rollback() {
+    let author = || Author {
+        nick: String::from("op"),
+        channel: String::from("#factorio"),
+        server: String::from("irc.libera.chat"),
+    };
+    let mut command = Command {
+        trigger: String::from("bugs"),
+        response: Vec::new(),
+        scope: Scope::Global,
+        cooldown: None,
+        user_cooldown: None,
+        history: Vec::new(),
+    };
+    command.change(author(), Change::Edit, vec![String::from("first")]);
+    command.change(author(), Change::Edit, vec![String::from("second")]);
+    command.rename(author(), String::from("bug"));
+    command.change(author(), Change::Forget, Vec::new());
+    assert!(command.is_forgotten());
+
+    let now = command.history[0].timestamp;
+    assert_eq!(
+        command.describe_history(now),
+        [
+            "#4 forgotten by op in #factorio on irc.libera.chat just now, was \"second\"",
+            "#3 renamed from \"bugs\" by op in #factorio on irc.libera.chat just now",
+            "#2 changed by op in #factorio on irc.libera.chat just now, was \"first\"",
+            "#1 created by op in #factorio on irc.libera.chat just now",
+        ]
+    );
+
+    assert_eq!(command.rollback(author(), 4), Ok(()));
+    assert_eq!(command.response, ["second"]);
+    assert!(command.rollback(author(), 3).is_err());
+    assert_eq!(command.rollback(author(), 2), Ok(()));
+    assert_eq!(command.response, ["first"]);
+    assert!(command.rollback(author(), 1).is_err());
+    assert!(command.rollback(author(), 9).is_err());
+    assert_eq!(command.response, ["first"]);
+    assert_eq!(command.history.len(), 6);
 }
@@ -0,0 +1,71 @@
+//! Cooldowns
+//!
+//! Keeps track of when commands were last used, so the bot can't be used to spam a channel.
+//! Cooldowns are tracked per server, channel and command, and optionally per user. Channel ops
+//! are never limited.
+
+use super::Invocation;
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+#[derive(PartialEq, Eq, Hash)]
+enum Key {
+    Channel {
+        server: String,
+        channel: String,
+        command: String,
+    },
+    User {
+        server: String,
+        user: String,
+        command: String,
+    },
+}
+
+lazy_static::lazy_static! {
+    /// The moment until which a command can not be used again
+    static ref COOLDOWNS: Mutex<HashMap<Key, Instant>> = Mutex::new(HashMap::new());
+}
+
+/// Check if `command` can be invoked. If it can, the cooldowns are started and `true` is returned.
+///
+/// `channel_cooldown` applies to everyone in the channel the command was invoked in,
+/// `user_cooldown` applies to the user who invoked it, in every channel on the server.
+pub fn try_invoke(
+    invocation: &Invocation,
+    command: &str,
+    channel_cooldown: Duration,
+    user_cooldown: Option<Duration>,
+) -> bool {
+    if invocation.sender_is_op() {
+        return true;
+    }
+
+    let server = &invocation.server_config.host;
+    let channel_key = Key::Channel {
+        server: server.clone(),
+        channel: invocation.reply_to.to_lowercase(),
+        command: command.to_owned(),
+    };
+    let user_key = Key::User {
+        server: server.clone(),
+        user: invocation.sender.to_lowercase(),
+        command: command.to_owned(),
+    };
+
+    let now = Instant::now();
+    let mut cooldowns = COOLDOWNS.lock();
+    cooldowns.retain(|_, until| *until > now);
+
+    if cooldowns.contains_key(&channel_key) || cooldowns.contains_key(&user_key) {
+        return false;
+    }
+    cooldowns.insert(channel_key, now + channel_cooldown);
+    if let Some(user_cooldown) = user_cooldown {
+        cooldowns.insert(user_key, now + user_cooldown);
+    }
+    true
+}
@@ -0,0 +1,181 @@
+//! Factorio version
+//!
+//! Knows the latest stable and experimental versions of Factorio, from the latest-releases api on
+//! factorio.com.
+//!
+//! `!version` replies with both versions and links to their changelogs, which are cached for a few
+//! minutes. New versions are announced by the feed watcher, with a feed that uses the
+//! `factorio_release` parser for either branch.
+
+use super::{cache::Cache, feed_watcher::FeedItem, Action, CommandInfo, Invocation};
+use crate::error::Error;
+use futures::future::BoxFuture;
+use std::time::Duration;
+
+pub const LATEST_RELEASES_URL: &str = "https://factorio.com/api/latest-releases";
+const CACHE_SECONDS: u64 = 60 * 5;
+
+lazy_static::lazy_static! {
+    static ref CACHE: Cache<Versions> = Cache::new(Duration::from_secs(CACHE_SECONDS));
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Branch {
+    Stable,
+    Experimental,
+}
+
+impl Branch {
+    fn name(self) -> &'static str {
+        match self {
+            Branch::Stable => "stable",
+            Branch::Experimental => "experimental",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+struct LatestReleases {
+    stable: Builds,
+    experimental: Builds,
+}
+
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+struct Builds {
+    /// The base game. The other builds (demo, headless, expansion) follow the same versions.
+    alpha: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Versions {
+    pub stable: String,
+    pub experimental: String,
+}
+
+impl Versions {
+    pub fn get(&self, branch: Branch) -> &str {
+        match branch {
+            Branch::Stable => &self.stable,
+            Branch::Experimental => &self.experimental,
+        }
+    }
+
+    /// The version of `branch` as a feed item: the id is the version, the link is the changelog
+    pub fn feed_item(&self, branch: Branch) -> FeedItem {
+        let version = self.get(branch);
+        FeedItem {
+            id: version.to_owned(),
+            title: format!("Factorio {} ({})", version, branch.name()),
+            link: changelog_url(version),
+        }
+    }
+}
+
+pub struct FactorioVersion;
+
+impl Action for FactorioVersion {
+    fn name(&self) -> &'static str {
+        "factorio_version"
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        &[CommandInfo {
+            name: "version",
+            aliases: &["latest"],
+            help: "Shows the latest stable and experimental versions of Factorio",
+        }]
+    }
+
+    fn on_command<'a>(
+        &'a self,
+        invocation: &'a Invocation<'a>,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(on_command(invocation))
+    }
+}
+
+async fn on_command<'a>(invocation: &'a Invocation<'a>) -> Result<(), Error> {
+    match CACHE
+        .get_or_fetch(LATEST_RELEASES_URL, latest_versions)
+        .await
+    {
+        Ok(versions) => invocation.reply(describe(&versions)),
+        Err(e) => {
+            e.report("Could not load the latest versions");
+            invocation.reply("Could not load the latest versions");
+        }
+    }
+    Ok(())
+}
+
+fn describe(versions: &Versions) -> String {
+    if versions.stable == versions.experimental {
+        format!(
+            "Latest version: {} (stable and experimental) {}",
+            versions.stable,
+            changelog_url(&versions.stable)
+        )
+    } else {
+        format!(
+            "Stable: {} {} | Experimental: {} {}",
+            versions.stable,
+            changelog_url(&versions.stable),
+            versions.experimental,
+            changelog_url(&versions.experimental)
+        )
+    }
+}
+
+pub async fn latest_versions() -> Result<Versions, Error> {
+    let text = crate::http::get_text(LATEST_RELEASES_URL).await?;
+    parse_latest_releases(&text)
+}
+
+pub fn parse_latest_releases(text: &str) -> Result<Versions, Error> {
+    let releases: LatestReleases = serde_json::from_str(text)?;
+    Ok(Versions {
+        stable: releases.stable.alpha,
+        experimental: releases.experimental.alpha,
+    })
+}
+
+/// The changelog of a version on the wiki, e.g. `Version_history/1.1.0#1.1.110` for 1.1.110
+pub fn changelog_url(version: &str) -> String {
+    let major_minor = version.splitn(3, '.').take(2).collect::<Vec<_>>().join(".");
+    format!(
+        "https://wiki.factorio.com/Version_history/{}.0#{}",
+        major_minor, version
+    )
+}
+
+#[tokio::test]
+async fn load_latest_versions() {
+    let versions = latest_versions()
+        .await
+        .expect("Could not load the latest versions");
+    println!("{}", describe(&versions));
+}
+
+#[test]
+fn test_parse_latest_releases() {
+    let json = r#"{
+        "experimental": {"alpha": "2.0.8", "demo": "1.1.110", "expansion": "2.0.8", "headless": "2.0.8"},
+        "stable": {"alpha": "1.1.110", "demo": "1.1.110", "headless": "1.1.110"}
+    }"#;
+    let versions = parse_latest_releases(json).expect("Could not parse latest releases");
+    assert_eq!(versions.stable, "1.1.110");
+    assert_eq!(versions.experimental, "2.0.8");
+    assert_eq!(
+        versions.feed_item(Branch::Experimental),
+        FeedItem {
+            id: String::from("2.0.8"),
+            title: String::from("Factorio 2.0.8 (experimental)"),
+            link: String::from("https://wiki.factorio.com/Version_history/2.0.0#2.0.8"),
+        }
+    );
+    assert_eq!(
+        describe(&versions),
+        "Stable: 1.1.110 https://wiki.factorio.com/Version_history/1.1.0#1.1.110 | Experimental: 2.0.8 https://wiki.factorio.com/Version_history/2.0.0#2.0.8"
+    );
+}
@@ -0,0 +1,490 @@
+//! Feed watcher
+//!
+//! Polls the feeds in the `feeds` section of a server in config.json at a regular interval. If a
+//! feed has a new item, it is announced in the configured channels, and optionally put in one of
+//! the named segments of the channel topic. Servers without a `feeds` section get the
+//! [`default_feeds`] in their `factorio_channel`.
+//!
+//! A feed is parsed as one of:
+//! - `rss`: the first `<item>` of an RSS feed
+//! - `atom`: the first `<entry>` of an Atom feed
+//! - `json_pointer`: values in a json document, selected with json pointers
+//! - `regex`: the first match of a regex in any text, e.g. an html page. The named groups `id`,
+//!   `title` and `link` are used, if there is no `id` group the first group is used instead.
+//! - `factorio_release`: the latest Factorio version of the given `branch` (`stable` or
+//!   `experimental`) from the latest-releases api. The link is the changelog of that version.
+//!
+//! The announcement and topic templates can contain `{id}`, `{title}` and `{link}`.
+//!
+//! A feed with a `command` can also be queried on demand: `!fff` replies with the latest item, and
+//! if the feed has a `lookup_url`, `!fff 300` replies with the title of the page for item 300.
+//! Results are cached for a few minutes.
+
+use super::{
+    cache::Cache,
+    factorio_version::{self, Branch},
+    last_seen::{self, Seen},
+    Action, Invocation,
+};
+use crate::{data::Client, error::Error, http, ConfigServer};
+use futures::future::{self, BoxFuture};
+use regex::Regex;
+use serde_json::Value;
+use std::time::Duration;
+use tracing::Instrument;
+
+pub const NAME: &str = "feed_watcher";
+const CACHE_SECONDS: u64 = 60 * 5;
+
+lazy_static::lazy_static! {
+    static ref CACHE: Cache<FeedItem> = Cache::new(Duration::from_secs(CACHE_SECONDS));
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FeedConfig {
+    /// Used to identify this feed in logs and in `persist/last_seen.json`
+    pub name: String,
+    pub url: String,
+    pub parser: FeedParser,
+    /// The message that is sent to `channels` when there is a new item
+    pub announcement: String,
+    pub channels: Vec<String>,
+    /// The name of the topic segment that should be replaced, see `topic_segments`
+    #[serde(default)]
+    pub topic_segment: Option<String>,
+    /// The text that is put in `topic_segment`. Defaults to `announcement`.
+    #[serde(default)]
+    pub topic_template: Option<String>,
+    #[serde(default = "default_interval_seconds")]
+    pub interval_seconds: u64,
+    /// The command that replies with the latest item, e.g. `fff`
+    #[serde(default)]
+    pub command: Option<String>,
+    /// The reply to `command`. Defaults to `{title} {link}`.
+    #[serde(default)]
+    pub reply: Option<String>,
+    /// The page of a single item, where `{id}` is replaced by the argument of `command`
+    #[serde(default)]
+    pub lookup_url: Option<String>,
+}
+
+fn default_interval_seconds() -> u64 {
+    60 * 10
+}
+
+/// The topic segments of `factorio_channel` that [`default_feeds`] write to, if `topic_segments`
+/// does not name them
+pub const DEFAULT_TOPIC_SEGMENTS: [&str; 4] = ["welcome", "stable", "experimental", "fff"];
+
+/// The feeds that are announced in `channel` if a server has no `feeds` section: both Factorio
+/// release branches, Friday Facts and Alt-F4, the same as in config.json.example.
+pub fn default_feeds(channel: &str) -> Vec<FeedConfig> {
+    let feed = |name: &str, url: &str, parser: FeedParser, announcement: &str| FeedConfig {
+        name: name.to_owned(),
+        url: url.to_owned(),
+        parser,
+        announcement: announcement.to_owned(),
+        channels: vec![channel.to_owned()],
+        topic_segment: None,
+        topic_template: None,
+        interval_seconds: default_interval_seconds(),
+        command: None,
+        reply: None,
+        lookup_url: None,
+    };
+    vec![
+        FeedConfig {
+            topic_segment: Some(String::from("stable")),
+            topic_template: Some(String::from("Stable: {id}")),
+            ..feed(
+                "factorio_stable",
+                factorio_version::LATEST_RELEASES_URL,
+                FeedParser::FactorioRelease {
+                    branch: Branch::Stable,
+                },
+                "New stable version {id} released. Changelog: {link}",
+            )
+        },
+        FeedConfig {
+            topic_segment: Some(String::from("experimental")),
+            topic_template: Some(String::from("Experimental: {id}")),
+            ..feed(
+                "factorio_experimental",
+                factorio_version::LATEST_RELEASES_URL,
+                FeedParser::FactorioRelease {
+                    branch: Branch::Experimental,
+                },
+                "New experimental version {id} released. Changelog: {link}",
+            )
+        },
+        FeedConfig {
+            topic_segment: Some(String::from("fff")),
+            topic_template: Some(String::from("{title}: {link}")),
+            command: Some(String::from("fff")),
+            lookup_url: Some(String::from("https://factorio.com/blog/post/fff-{id}")),
+            ..feed(
+                "friday_facts",
+                "https://factorio.com/blog/rss",
+                FeedParser::Rss,
+                "New factorio farts: {title} {link}",
+            )
+        },
+        FeedConfig {
+            command: Some(String::from("altf4")),
+            ..feed(
+                "alt_f4",
+                "https://alt-f4.blog/index.xml",
+                FeedParser::Rss,
+                "New Alt-f4 facts: {title} {link}",
+            )
+        },
+    ]
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum FeedParser {
+    Rss,
+    Atom,
+    JsonPointer {
+        id: String,
+        #[serde(default)]
+        title: Option<String>,
+        #[serde(default)]
+        link: Option<String>,
+    },
+    Regex {
+        pattern: String,
+    },
+    FactorioRelease {
+        branch: Branch,
+    },
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FeedItem {
+    /// Uniquely identifies this item, a new id means there is a new item
+    pub id: String,
+    pub title: String,
+    pub link: String,
+}
+
+impl FeedItem {
+    /// Fill in `{id}`, `{title}` and `{link}` in the given template
+    pub fn format(&self, template: &str) -> String {
+        template
+            .replace("{id}", &self.id)
+            .replace("{title}", &self.title)
+            .replace("{link}", &self.link)
+    }
+}
+
+pub struct FeedWatcher;
+
+impl Action for FeedWatcher {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn on_start(&self, client: &Client) -> Result<(), Error> {
+        for feed in client.server_config().feeds().iter().cloned() {
+            if let FeedParser::Regex { pattern } = &feed.parser {
+                // One broken feed should not keep the others from starting
+                if let Err(e) = Regex::new(pattern) {
+                    Error::Config(format!("Invalid regex in {}: {}", feed.name, e))
+                        .report("Could not start feed");
+                    continue;
+                }
+            }
+            spawn(client.clone(), feed);
+        }
+        Ok(())
+    }
+
+    fn handles_unknown_commands(&self) -> bool {
+        true
+    }
+
+    fn on_command<'a>(
+        &'a self,
+        invocation: &'a Invocation<'a>,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(on_command(invocation))
+    }
+}
+
+/// The feeds on this server that can be queried with a command
+pub fn feed_commands(server_config: &ConfigServer) -> impl Iterator<Item = &FeedConfig> {
+    server_config
+        .feeds()
+        .iter()
+        .filter(|feed| feed.command.is_some())
+}
+
+async fn on_command<'a>(invocation: &'a Invocation<'a>) -> Result<(), Error> {
+    let feed = match feed_commands(invocation.server_config)
+        .find(|feed| feed.command.as_deref() == Some(invocation.command.as_str()))
+    {
+        Some(feed) => feed,
+        None => return Ok(()),
+    };
+    let reply = feed.reply.as_deref().unwrap_or("{title} {link}");
+
+    let result = match (invocation.args.first(), &feed.lookup_url) {
+        (None, _) => CACHE.get_or_fetch(&cache_key(feed), || fetch(feed)).await,
+        (Some(id), Some(lookup_url)) => {
+            let id = url::form_urlencoded::byte_serialize(id.as_bytes()).collect::<String>();
+            let url = lookup_url.replace("{id}", &id);
+            CACHE.get_or_fetch(&url, || lookup(id, url.clone())).await
+        }
+        (Some(_), None) => {
+            invocation.reply(format!(
+                "Usage: {}{}",
+                invocation.server_config.command_prefix, invocation.command
+            ));
+            return Ok(());
+        }
+    };
+    match result {
+        Ok(item) => invocation.reply(item.format(reply).trim()),
+        Err(e) => {
+            e.report(&format!("Could not query feed {:?}", feed.name));
+            invocation.reply("Could not find that, try again later");
+        }
+    }
+    Ok(())
+}
+
+fn cache_key(feed: &FeedConfig) -> String {
+    format!("{} {}", feed.name, feed.url)
+}
+
+/// Load a single item from its page, using the html `<title>` as the title
+async fn lookup(id: String, url: String) -> Result<FeedItem, Error> {
+    let html = http::get_text(&url).await?;
+    Ok(FeedItem {
+        id,
+        title: html_title(&html).unwrap_or_default(),
+        link: url,
+    })
+}
+
+fn html_title(html: &str) -> Option<String> {
+    let start = html.find("<title")?;
+    let start = start + html[start..].find('>')? + 1;
+    let end = start + html[start..].find("</title>")?;
+    Some(http::unescape_html(html[start..end].trim()))
+}
+
+fn spawn(client: Client, feed: FeedConfig) {
+    tokio::spawn(poll(client, feed).in_current_span());
+}
+
+async fn poll(client: Client, feed: FeedConfig) {
+    let server = client.server_config().host;
+    let interval = Duration::from_secs(feed.interval_seconds);
+    client.wait_for_channels(&feed.channels).await;
+    while client.running() {
+        let delay = match fetch(&feed).await {
+            // The client could have disconnected while we were fetching, in which case the client
+            // that reconnects will announce this item
+            Ok(_) if !client.running() => break,
+            Ok(item) => {
+                // Until the bot is in every channel the item is kept back, so that it is not
+                // marked as seen while a channel never got it
+                if last_seen::check(&server, &feed.name, &item.id) == Seen::New
+                    && client.in_channels(&feed.channels)
+                    && announce(&client, &feed, &item).await
+                {
+                    last_seen::mark(&server, &feed.name, &item.id);
+                }
+                CACHE.insert(&cache_key(&feed), item);
+                interval
+            }
+            Err(e) => {
+                e.report(&format!("Cannot poll feed {:?}", feed.name));
+                e.retry_delay(interval)
+            }
+        };
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Announce `item` in every channel of `feed`. Returns whether all announcements were sent.
+async fn announce(client: &Client, feed: &FeedConfig, item: &FeedItem) -> bool {
+    let mut sent = Vec::new();
+    for channel_name in &feed.channels {
+        if let Some(segment) = &feed.topic_segment {
+            let template = feed.topic_template.as_ref().unwrap_or(&feed.announcement);
+            if let Err(e) = client.set_topic_segment(channel_name, segment, item.format(template)) {
+                e.report(&format!("Could not update the topic of {}", channel_name));
+            }
+        }
+        sent.push(client.send_to_channel(channel_name, item.format(&feed.announcement)));
+    }
+    future::join_all(sent.into_iter().map(|sent| sent.wait()))
+        .await
+        .into_iter()
+        .all(|sent| sent)
+}
+
+/// Load the latest item of the given feed
+pub async fn fetch(feed: &FeedConfig) -> Result<FeedItem, Error> {
+    let response = http::get_text(&feed.url).await?;
+    parse(&feed.parser, &response)
+}
+
+fn parse(parser: &FeedParser, text: &str) -> Result<FeedItem, Error> {
+    match parser {
+        FeedParser::Rss => parse_xml(text, "item", "guid", |node| {
+            child_text(node, "link").unwrap_or_default()
+        }),
+        FeedParser::Atom => parse_xml(text, "entry", "id", |node| {
+            node.children()
+                .find(|c| c.has_tag_name("link"))
+                .and_then(|link| link.attribute("href"))
+                .unwrap_or_default()
+                .to_owned()
+        }),
+        FeedParser::JsonPointer { id, title, link } => {
+            let json: Value = serde_json::from_str(text)?;
+            let get = |pointer: &str| match json.pointer(pointer) {
+                Some(Value::String(s)) => Some(s.clone()),
+                Some(Value::Null) | None => None,
+                Some(value) => Some(value.to_string()),
+            };
+            Ok(FeedItem {
+                id: get(id).ok_or_else(|| Error::Parse(format!("Could not find {:?}", id)))?,
+                title: title.as_deref().and_then(get).unwrap_or_default(),
+                link: link.as_deref().and_then(get).unwrap_or_default(),
+            })
+        }
+        FeedParser::Regex { pattern } => {
+            let regex = Regex::new(pattern).map_err(|e| Error::Config(e.to_string()))?;
+            let captures = regex
+                .captures(text)
+                .ok_or_else(|| Error::Parse(format!("Could not find {:?}", pattern)))?;
+            let get = |name: &str| {
+                captures
+                    .name(name)
+                    .map(|m| http::unescape_html(m.as_str()))
+                    .unwrap_or_default()
+            };
+            let id = captures
+                .name("id")
+                .or_else(|| captures.get(1))
+                .map(|m| http::unescape_html(m.as_str()))
+                .unwrap_or_default();
+            Ok(FeedItem {
+                id,
+                title: get("title"),
+                link: get("link"),
+            })
+        }
+        FeedParser::FactorioRelease { branch } => {
+            Ok(factorio_version::parse_latest_releases(text)?.feed_item(*branch))
+        }
+    }
+}
+
+/// Parse the first `item_tag` element of an xml feed. If it has no `id_tag`, the link is used as
+/// the id.
+fn parse_xml(
+    text: &str,
+    item_tag: &str,
+    id_tag: &str,
+    get_link: impl Fn(roxmltree::Node) -> String,
+) -> Result<FeedItem, Error> {
+    let document = roxmltree::Document::parse(text).map_err(|e| Error::Parse(e.to_string()))?;
+    let item = document
+        .descendants()
+        .find(|n| n.has_tag_name(item_tag))
+        .ok_or_else(|| Error::Parse(format!("Feed has no <{}>", item_tag)))?;
+
+    let link = get_link(item);
+    Ok(FeedItem {
+        id: child_text(item, id_tag).unwrap_or_else(|| link.clone()),
+        title: child_text(item, "title").unwrap_or_default(),
+        link,
+    })
+}
+
+fn child_text(node: roxmltree::Node, tag: &str) -> Option<String> {
+    node.children()
+        .find(|c| c.has_tag_name(tag))
+        .and_then(|c| c.text())
+        .map(|t| t.trim().to_owned())
+}
+
+#[tokio::test]
+async fn load_example_feeds() {
+    let config = crate::Config::from_file("config.json.example").expect("Could not load config");
+    for feed in config.servers.iter().flat_map(|s| s.feeds()) {
+        let item = fetch(feed).await.expect("Could not load feed");
+        println!("{}: {:?}", feed.name, item);
+        assert!(!item.id.is_empty(), "Feed {} has no id", feed.name);
+    }
+}
+
+#[test]
+fn test_parse() {
+    let rss = r#"<?xml version="1.0"?><rss><channel><title>Blog</title>
+        <item><title>Post 2</title><link>https://example.com/2</link><guid>2</guid></item>
+        <item><title>Post 1</title><link>https://example.com/1</link><guid>1</guid></item>
+        </channel></rss>"#;
+    assert_eq!(
+        parse(&FeedParser::Rss, rss),
+        Ok(FeedItem {
+            id: String::from("2"),
+            title: String::from("Post 2"),
+            link: String::from("https://example.com/2"),
+        })
+    );
+
+    let atom = r#"<feed xmlns="http://www.w3.org/2005/Atom"><title>Videos</title>
+        <entry><id>yt:video:abc</id><title>Video</title><link rel="alternate" href="https://youtu.be/abc"/></entry>
+        </feed>"#;
+    assert_eq!(
+        parse(&FeedParser::Atom, atom),
+        Ok(FeedItem {
+            id: String::from("yt:video:abc"),
+            title: String::from("Video"),
+            link: String::from("https://youtu.be/abc"),
+        })
+    );
+
+    let json = r#"{"stable": {"alpha": "1.1.110"}}"#;
+    let parser = FeedParser::JsonPointer {
+        id: String::from("/stable/alpha"),
+        title: None,
+        link: Some(String::from("/missing")),
+    };
+    assert_eq!(
+        parse(&parser, json),
+        Ok(FeedItem {
+            id: String::from("1.1.110"),
+            ..FeedItem::default()
+        })
+    );
+
+    let html = r#"<a href="./viewtopic.php?f=3&amp;t=1" class="topictitle">Version 1.1.2</a>"#;
+    let parser = FeedParser::Regex {
+        pattern: String::from(
+            r#"<a href="\./(?P<link>[^"]*)"[^>]*topictitle">Version (?P<id>[^<]*)<"#,
+        ),
+    };
+    let item = parse(&parser, html).expect("Could not parse html");
+    assert_eq!(item.id, "1.1.2");
+    assert_eq!(item.link, "viewtopic.php?f=3&t=1");
+    assert_eq!(
+        item.format("Version {id} released. https://forums.factorio.com/{link}"),
+        "Version 1.1.2 released. https://forums.factorio.com/viewtopic.php?f=3&t=1"
+    );
+
+    let html = "<html><head><title>\n  Friday Facts #300 - Quality &amp; more | Factorio\n</title>";
+    assert_eq!(
+        html_title(html).as_deref(),
+        Some("Friday Facts #300 - Quality & more | Factorio")
+    );
+    assert_eq!(html_title("<html></html>"), None);
+}
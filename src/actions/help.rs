@@ -0,0 +1,184 @@
+//! Help
+//!
+//! `!help` lists every command that can be used in the current channel. If that list does not fit
+//! in a single message, it is sent to the user as a NOTICE instead, split over multiple lines.
+//!
+//! `!help <command>` shows what a single command does.
+
+use super::{
+    commands, cooldown, enabled_actions, feed_watcher, resolve_alias, Action, CommandInfo,
+    Invocation,
+};
+use crate::error::Error;
+use futures::future::BoxFuture;
+use std::time::Duration;
+
+/// The maximum amount of bytes of commands that are sent in a single line
+const MAX_LINE_LENGTH: usize = 350;
+const HELP_COOLDOWN_SECONDS: u64 = 60;
+
+pub struct Help;
+
+impl Action for Help {
+    fn name(&self) -> &'static str {
+        "help"
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        &[CommandInfo {
+            name: "help",
+            aliases: &["commands"],
+            help:
+                "Lists all commands, or explains what a single command does. Usage: !help [command]",
+        }]
+    }
+
+    fn on_command<'a>(
+        &'a self,
+        invocation: &'a Invocation<'a>,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(on_command(invocation))
+    }
+}
+
+async fn on_command<'a>(invocation: &'a Invocation<'a>) -> Result<(), Error> {
+    match invocation.args.first() {
+        Some(command) => help_for_command(invocation, command),
+        None => list_commands(invocation),
+    }
+    Ok(())
+}
+
+fn list_commands(invocation: &Invocation) {
+    let prefix = &invocation.server_config.command_prefix;
+    let channel = invocation.channel.as_ref().map(|_| invocation.reply_to);
+
+    let mut names = enabled_actions(invocation.client, channel)
+        .flat_map(|action| action.commands())
+        .map(|info| info.name.to_owned())
+        .collect::<Vec<_>>();
+    if invocation
+        .server_config
+        .action_enabled(commands::NAME, channel)
+    {
+        names.extend(commands::visible_triggers(invocation));
+    }
+    if invocation
+        .server_config
+        .action_enabled(feed_watcher::NAME, channel)
+    {
+        names.extend(
+            feed_watcher::feed_commands(invocation.server_config)
+                .filter_map(|feed| feed.command.clone()),
+        );
+    }
+    names.sort_unstable();
+    names.dedup();
+
+    let lines = split_in_lines(names.iter().map(|name| format!("{}{}", prefix, name)));
+    let footer = format!("Use {}help <command> to see what a command does", prefix);
+
+    if lines.len() <= 1 {
+        let cooldown = Duration::from_secs(HELP_COOLDOWN_SECONDS);
+        if cooldown::try_invoke(invocation, "help", cooldown, None) {
+            invocation.reply(format!(
+                "Commands: {}. {}",
+                lines.first().map(String::as_str).unwrap_or_default(),
+                footer
+            ));
+        }
+    } else {
+        // Long lists go to the user directly, so they don't flood the channel
+        let cooldown = Duration::from_secs(HELP_COOLDOWN_SECONDS);
+        if cooldown::try_invoke(invocation, "help (private)", Duration::ZERO, Some(cooldown)) {
+            let count = lines.len();
+            for (index, line) in lines.into_iter().enumerate() {
+                invocation.reply_privately(format!("Commands ({}/{}): {}", index + 1, count, line));
+            }
+            invocation.reply_privately(footer);
+        }
+    }
+}
+
+fn help_for_command(invocation: &Invocation, command: &str) {
+    let prefix = &invocation.server_config.command_prefix;
+    let command = command
+        .strip_prefix(prefix.as_str())
+        .unwrap_or(command)
+        .to_lowercase();
+    let command = resolve_alias(invocation.server_config, &command).unwrap_or(command);
+    let channel = invocation.channel.as_ref().map(|_| invocation.reply_to);
+
+    let info = enabled_actions(invocation.client, channel)
+        .flat_map(|action| action.commands())
+        .find(|info| info.name == command);
+    let feed = feed_watcher::feed_commands(invocation.server_config)
+        .find(|feed| feed.command.as_deref() == Some(command.as_str()))
+        .filter(|_| {
+            invocation
+                .server_config
+                .action_enabled(feed_watcher::NAME, channel)
+        });
+    if let Some(info) = info {
+        let aliases = if info.aliases.is_empty() {
+            String::new()
+        } else {
+            let aliases = info
+                .aliases
+                .iter()
+                .map(|alias| format!("{}{}", prefix, alias))
+                .collect::<Vec<_>>();
+            format!(" (also {})", aliases.join(", "))
+        };
+        invocation.reply(format!("{}{}{}: {}", prefix, info.name, aliases, info.help));
+    } else if let Some(feed) = feed {
+        let lookup = if feed.lookup_url.is_some() {
+            format!(", or a specific one with {}{} <number>", prefix, command)
+        } else {
+            String::new()
+        };
+        invocation.reply(format!(
+            "{}{}: Shows the latest item of {}{}",
+            prefix, command, feed.name, lookup
+        ));
+    } else if commands::visible_triggers(invocation).contains(&command) {
+        invocation.reply(format!(
+            "{}{} is a learned command, ops can change it with {}learn",
+            prefix, command, prefix
+        ));
+    } else {
+        invocation.reply(format!("Unknown command {}{}", prefix, command));
+    }
+}
+
+/// Join the items with `, `, starting a new line whenever a line would get longer than
+/// `MAX_LINE_LENGTH`
+fn split_in_lines(items: impl Iterator<Item = String>) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    for item in items {
+        if !current.is_empty() && current.len() + 2 + item.len() > MAX_LINE_LENGTH {
+            lines.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current += ", ";
+        }
+        current += &item;
+    }
+    if !current.is_empty() {
+        lines.push(current);
+    }
+    lines
+}
+
+#[test]
+fn test_split_in_lines() {
+    assert!(split_in_lines(std::iter::empty()).is_empty());
+
+    let items = (0..100).map(|i| format!("!command{}", i));
+    let lines = split_in_lines(items);
+    assert!(lines.len() > 1);
+    assert!(lines.iter().all(|line| line.len() <= MAX_LINE_LENGTH));
+    assert!(lines[0].starts_with("!command0, !command1, "));
+    assert_eq!(lines.join(", ").matches("!command").count(), 100);
+}
@@ -0,0 +1,170 @@
+//! Command parsing
+//!
+//! Every message that is addressed to the bot, either with the configured prefix (`!mp`) or by
+//! nickname (`TrangarBot: mp`), is turned into an [`Invocation`]. Aliases are resolved here, so
+//! actions only ever see the canonical name of a command.
+
+use crate::data::Message;
+use std::ops::Deref;
+
+/// A command that an action responds to
+pub struct CommandInfo {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    /// Shown in `!help <command>`
+    pub help: &'static str,
+}
+
+pub struct Invocation<'a> {
+    pub message: &'a Message<'a>,
+    /// The lowercased name of the command, with aliases resolved
+    pub command: String,
+    /// Everything after the command name, trimmed
+    pub raw_args: &'a str,
+    /// `raw_args` split on whitespace, where "quoted text" counts as a single argument
+    pub args: Vec<String>,
+}
+
+impl<'a> Deref for Invocation<'a> {
+    type Target = Message<'a>;
+
+    fn deref(&self) -> &Message<'a> {
+        self.message
+    }
+}
+
+impl<'a> Invocation<'a> {
+    /// Parse the given message. Returns `None` if the message is not addressed to the bot.
+    ///
+    /// `resolve_alias` is called with the lowercased command name and returns the canonical name
+    /// if the command is an alias.
+    pub fn parse(
+        message: &'a Message<'a>,
+        resolve_alias: impl FnOnce(&str) -> Option<String>,
+    ) -> Option<Self> {
+        let (command, raw_args) = split_command(
+            message.body,
+            &message.server_config.command_prefix,
+            &message.server_config.nickname,
+        )?;
+        let command = command.to_lowercase();
+        let command = resolve_alias(&command).unwrap_or(command);
+
+        Some(Self {
+            message,
+            command,
+            raw_args,
+            args: tokenize(raw_args),
+        })
+    }
+
+    /// Whether the user that sent this command is an op in the channel it was sent in
+    pub fn sender_is_op(&self) -> bool {
+        self.channel
+            .as_ref()
+            .map(|c| c.user_is_op(self.sender))
+            .unwrap_or(false)
+    }
+}
+
+/// Split a message in the command name and the remaining text, if the message starts with
+/// `prefix` or is addressed to `nickname` (e.g. `TrangarBot: mp` or `TrangarBot, !mp`).
+fn split_command<'a>(body: &'a str, prefix: &str, nickname: &str) -> Option<(&'a str, &'a str)> {
+    let body = body.trim();
+    let remaining = if !prefix.is_empty() && body.starts_with(prefix) {
+        &body[prefix.len()..]
+    } else {
+        let addressed = body
+            .get(..nickname.len())
+            .filter(|start| start.eq_ignore_ascii_case(nickname))
+            .map(|_| &body[nickname.len()..])
+            .and_then(|rest| rest.strip_prefix(':').or_else(|| rest.strip_prefix(',')))?
+            .trim_start();
+        addressed.strip_prefix(prefix).unwrap_or(addressed)
+    };
+
+    let end = remaining
+        .find(char::is_whitespace)
+        .unwrap_or(remaining.len());
+    let (command, raw_args) = remaining.split_at(end);
+    if command.is_empty() {
+        None
+    } else {
+        Some((command, raw_args.trim()))
+    }
+}
+
+/// Split the arguments on whitespace. Text in double quotes is kept together, and `\"` can be
+/// used to put a quote inside of quoted text.
+pub fn tokenize(text: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut current = String::new();
+    let mut in_token = false;
+    let mut in_quotes = false;
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                in_token = true;
+            }
+            '\\' if in_quotes => {
+                if let Some(next) = chars.next() {
+                    current.push(next);
+                }
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if in_token {
+                    result.push(std::mem::take(&mut current));
+                    in_token = false;
+                }
+            }
+            c => {
+                current.push(c);
+                in_token = true;
+            }
+        }
+    }
+    if in_token {
+        result.push(current);
+    }
+    result
+}
+
+#[test]
+fn test_split_command() {
+    assert_eq!(split_command("!mp", "!", "TrangarBot"), Some(("mp", "")));
+    assert_eq!(
+        split_command("  !wa  machine train ", "!", "TrangarBot"),
+        Some(("wa", "machine train"))
+    );
+    assert_eq!(
+        split_command("trangarbot: mp", "!", "TrangarBot"),
+        Some(("mp", ""))
+    );
+    assert_eq!(
+        split_command("TrangarBot, !wa pi", "!", "TrangarBot"),
+        Some(("wa", "pi"))
+    );
+    assert_eq!(
+        split_command(".learn x = y", ".", "TrangarBot"),
+        Some(("learn", "x = y"))
+    );
+    assert_eq!(split_command("!", "!", "TrangarBot"), None);
+    assert_eq!(
+        split_command("TrangarBot is great", "!", "TrangarBot"),
+        None
+    );
+    assert_eq!(split_command("hello", "!", "TrangarBot"), None);
+}
+
+#[test]
+fn test_tokenize() {
+    assert_eq!(tokenize(""), Vec::<String>::new());
+    assert_eq!(tokenize("a  b c"), vec!["a", "b", "c"]);
+    assert_eq!(
+        tokenize(r#"add "two words" "say \"hi\"" """#),
+        vec!["add", "two words", "say \"hi\"", ""]
+    );
+}
@@ -0,0 +1,64 @@
+//! Last seen
+//!
+//! Remembers the last item that every poller has seen, so that new items are announced exactly
+//! once, even if the bot restarts or reconnects in between. A new item is only remembered once
+//! its announcement has been sent, so an announcement that is lost because the bot disconnects
+//! is sent again after reconnecting. Pollers also hold an item back until the bot has joined every
+//! channel it goes to.
+
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use tracing::error;
+
+const FILE: &str = "persist/last_seen.json";
+
+lazy_static::lazy_static! {
+    /// The id of the last seen item, by server and poller
+    static ref LAST_SEEN: Mutex<HashMap<String, HashMap<String, String>>> =
+        Mutex::new(crate::persist::load(FILE).unwrap_or_else(|e| {
+            e.report("Could not load the last seen items, starting without them");
+            HashMap::new()
+        }));
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Seen {
+    /// This poller has never seen an item before. This happens the first time a poller runs, in
+    /// which case the item should not be announced.
+    First,
+    /// The item was seen before
+    Same,
+    /// The item is new and should be announced
+    New,
+}
+
+/// Check if `id` is new for `poller` on `server`. The first item a poller sees is remembered
+/// straight away, because it is never announced. A new item has to be marked as seen with
+/// [`mark`] once it has been announced.
+pub fn check(server: &str, poller: &str, id: &str) -> Seen {
+    let seen = match LAST_SEEN
+        .lock()
+        .get(server)
+        .and_then(|pollers| pollers.get(poller))
+    {
+        None => Seen::First,
+        Some(last_id) if last_id == id => Seen::Same,
+        Some(_) => Seen::New,
+    };
+    if seen == Seen::First {
+        mark(server, poller, id);
+    }
+    seen
+}
+
+/// Mark `id` as the last item seen by `poller` on `server`
+pub fn mark(server: &str, poller: &str, id: &str) {
+    let mut last_seen = LAST_SEEN.lock();
+    last_seen
+        .entry(server.to_owned())
+        .or_default()
+        .insert(poller.to_owned(), id.to_owned());
+    if let Err(e) = crate::persist::save(FILE, &*last_seen) {
+        error!(error = %e, "Could not save the last seen items");
+    }
+}
@@ -1,28 +1,192 @@
 mod autojoin;
-mod check_alt4_blog;
-mod check_factorio_friday_facts;
-mod check_factorio_version;
+mod cache;
 mod commands;
+mod cooldown;
+mod factorio_version;
+mod feed_watcher;
+mod help;
+mod invocation;
+mod last_seen;
+mod mod_portal;
 mod multiplayer_info;
+mod status;
+mod template;
+mod topic;
+mod wiki;
 mod wolfram_alpha;
+mod youtube;
 
-use crate::data::{Client, Message};
+pub use self::{
+    feed_watcher::{default_feeds, FeedConfig, DEFAULT_TOPIC_SEGMENTS},
+    invocation::{CommandInfo, Invocation},
+    youtube::YoutubeChannelConfig,
+};
 
-pub async fn on_start(client: Client) -> Result<(), String> {
-    if let Some(factorio_channel) = client.server_config().factorio_channel {
-        check_factorio_version::spawn(client.clone(), factorio_channel.clone());
-        check_alt4_blog::spawn(client.clone(), factorio_channel.clone());
-        check_factorio_friday_facts::spawn(client, factorio_channel);
+use crate::{
+    data::{Client, Join, Message},
+    error::Error,
+    ConfigServer,
+};
+use futures::future::{self, BoxFuture};
+use tracing::info_span;
+
+/// A single feature of the bot.
+///
+/// Every hook has a default implementation that does nothing, so an action only has to implement
+/// the hooks it is interested in. Actions can be enabled and disabled per server and per channel
+/// in config.json by their [`name`](Action::name).
+pub trait Action: Send + Sync {
+    /// The name this action is referred to by in config.json
+    fn name(&self) -> &'static str;
+
+    /// Called every time the client (re)connects to a server
+    fn on_start(&self, _client: &Client) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Called when the client disconnects from a server
+    fn on_stop(&self, _client: &Client) {}
+
+    /// Called for every PRIVMSG received, in a channel or in private
+    fn on_message<'a>(&'a self, _message: &'a Message<'a>) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(future::ok(()))
+    }
+
+    /// The commands this action responds to
+    fn commands(&self) -> &'static [CommandInfo] {
+        &[]
+    }
+
+    /// Whether this action wants to receive commands that no action has claimed in
+    /// [`commands`](Action::commands), e.g. because its commands are defined at runtime
+    fn handles_unknown_commands(&self) -> bool {
+        false
+    }
+
+    /// Called when one of this action's commands is invoked
+    fn on_command<'a>(
+        &'a self,
+        _invocation: &'a Invocation<'a>,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(future::ok(()))
+    }
+
+    /// Called when a user joins a channel
+    fn on_join(&self, _join: &Join) {}
+}
+
+lazy_static::lazy_static! {
+    static ref ACTIONS: Vec<Box<dyn Action>> = vec![
+        Box::new(autojoin::Autojoin),
+        Box::new(commands::Commands),
+        Box::new(factorio_version::FactorioVersion),
+        Box::new(feed_watcher::FeedWatcher),
+        Box::new(help::Help),
+        Box::new(mod_portal::ModPortal),
+        Box::new(multiplayer_info::MultiplayerInfo),
+        Box::new(status::Status),
+        Box::new(topic::Topic),
+        Box::new(wiki::Wiki),
+        Box::new(wolfram_alpha::WolframAlpha),
+        Box::new(youtube::Youtube),
+    ];
+}
+
+fn enabled_actions<'a>(
+    client: &Client,
+    channel: Option<&'a str>,
+) -> impl Iterator<Item = &'static dyn Action> + 'a {
+    let server_config = client.server_config();
+    ACTIONS
+        .iter()
+        .map(|action| &**action)
+        .filter(move |action| server_config.action_enabled(action.name(), channel))
+}
+
+/// If `command` is an alias, either from config.json or from an action, returns the name of the
+/// command it is an alias of
+fn resolve_alias(server_config: &ConfigServer, command: &str) -> Option<String> {
+    if let Some(alias) = server_config.aliases.get(command) {
+        return Some(alias.to_lowercase());
+    }
+    ACTIONS
+        .iter()
+        .flat_map(|action| action.commands())
+        .find(|info| info.aliases.contains(&command))
+        .map(|info| info.name.to_owned())
+}
+
+/// Start every enabled action. An action that fails to start is reported, and does not keep the
+/// other actions from starting.
+pub async fn on_start(client: Client) {
+    for action in enabled_actions(&client, None) {
+        if let Err(e) = action.on_start(&client) {
+            e.report(&format!("Could not start {}", action.name()));
+        }
     }
-    commands::start();
-    Ok(())
 }
 
-pub async fn on_message<'a>(message: &'a Message<'a>) -> Result<(), String> {
-    futures::try_join!(
-        commands::on_message(message),
-        multiplayer_info::on_message(message),
-        wolfram_alpha::on_message(message),
-    )
-    .map(|_| ())
+pub fn on_stop(client: &Client) {
+    for action in enabled_actions(client, None) {
+        action.on_stop(client);
+    }
+}
+
+pub async fn on_message<'a>(message: &'a Message<'a>) {
+    let channel = message.channel.as_ref().map(|_| message.reply_to);
+    let actions = enabled_actions(message.client, channel).collect::<Vec<_>>();
+
+    let invocation = Invocation::parse(message, |command| {
+        resolve_alias(message.server_config, command)
+    });
+
+    let mut handlers = actions
+        .iter()
+        .map(|action| (*action, action.on_message(message)))
+        .collect::<Vec<_>>();
+    if let Some(invocation) = invocation.as_ref() {
+        let claims = |action: &dyn Action| {
+            action
+                .commands()
+                .iter()
+                .any(|info| info.name == invocation.command)
+        };
+        // Only fall back to e.g. learned commands if no action knows this command, even if the
+        // action that knows it is disabled here
+        let is_known = ACTIONS.iter().any(|action| claims(&**action));
+        handlers.extend(
+            actions
+                .iter()
+                .filter(|action| {
+                    if is_known {
+                        claims(**action)
+                    } else {
+                        action.handles_unknown_commands()
+                    }
+                })
+                .map(|action| (*action, action.on_command(invocation))),
+        );
+    }
+
+    let (actions, futures): (Vec<_>, Vec<_>) = handlers.into_iter().unzip();
+    let results = future::join_all(futures).await;
+
+    for (action, result) in actions.iter().zip(results) {
+        if let Err(e) = result {
+            let _span = info_span!(
+                "action",
+                action = action.name(),
+                sender = %message.sender,
+                body = %message.body
+            )
+            .entered();
+            e.report("Could not execute action");
+        }
+    }
+}
+
+pub fn on_join(join: &Join) {
+    for action in enabled_actions(join.client, Some(join.channel)) {
+        action.on_join(join);
+    }
 }
@@ -0,0 +1,280 @@
+//! Mod portal
+//!
+//! `!mod <name or search>` looks up a mod on mods.factorio.com. If there is no mod with that exact
+//! name, the first search result is used instead.
+//!
+//! New releases of the mods in `watched_mods` are announced in the `factorio_channel` of the
+//! server.
+
+use super::{
+    cache::Cache,
+    last_seen::{self, Seen},
+    Action, CommandInfo, Invocation,
+};
+use crate::{data::Client, error::Error, http};
+use futures::future::BoxFuture;
+use regex::Regex;
+use std::time::Duration;
+use tracing::Instrument;
+
+const API_URL: &str = "https://mods.factorio.com/api/mods";
+const CACHE_SECONDS: u64 = 60 * 5;
+const WATCH_INTERVAL_SECONDS: u64 = 60 * 15;
+
+lazy_static::lazy_static! {
+    static ref CACHE: Cache<ModInfo> = Cache::new(Duration::from_secs(CACHE_SECONDS));
+    static ref SEARCH_RESULT_REGEX: Regex =
+        Regex::new(r#"href="/mod/([^"?/]+)"#).expect("Could not compile search result regex");
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct ModInfo {
+    name: String,
+    title: String,
+    owner: String,
+    downloads_count: u64,
+    /// Only sent when looking up a single mod
+    #[serde(default)]
+    releases: Vec<Release>,
+    /// Only sent when listing mods
+    #[serde(default)]
+    latest_release: Option<Release>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct Release {
+    version: String,
+    info_json: ReleaseInfo,
+    released_at: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct ReleaseInfo {
+    factorio_version: String,
+}
+
+#[derive(Deserialize)]
+struct ModList {
+    results: Vec<ModInfo>,
+}
+
+impl ModInfo {
+    fn latest_release(&self) -> Option<&Release> {
+        self.latest_release.as_ref().or_else(|| {
+            self.releases
+                .iter()
+                .max_by(|a, b| a.released_at.cmp(&b.released_at))
+        })
+    }
+
+    fn link(&self) -> String {
+        let mut url = url::Url::parse("https://mods.factorio.com/mod/").expect("Invalid mod url");
+        url.path_segments_mut()
+            .expect("Invalid mod url")
+            .pop_if_empty()
+            .push(&self.name);
+        url.to_string()
+    }
+
+    fn describe(&self) -> String {
+        let release = match self.latest_release() {
+            Some(release) => format!(
+                "v{} for Factorio {}",
+                release.version, release.info_json.factorio_version
+            ),
+            None => String::from("no releases"),
+        };
+        format!(
+            "{} by {}: {}, {} downloads {}",
+            self.title,
+            self.owner,
+            release,
+            self.downloads_count,
+            self.link()
+        )
+    }
+}
+
+pub struct ModPortal;
+
+impl Action for ModPortal {
+    fn name(&self) -> &'static str {
+        "mod_portal"
+    }
+
+    fn on_start(&self, client: &Client) -> Result<(), Error> {
+        let server_config = client.server_config();
+        if let Some(channel) = server_config.factorio_channel {
+            if !server_config.watched_mods.is_empty() {
+                spawn(client.clone(), channel, server_config.watched_mods);
+            }
+        }
+        Ok(())
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        &[CommandInfo {
+            name: "mod",
+            aliases: &["mods"],
+            help: "Looks up a mod on the mod portal. Usage: !mod <name or search>",
+        }]
+    }
+
+    fn on_command<'a>(
+        &'a self,
+        invocation: &'a Invocation<'a>,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(on_command(invocation))
+    }
+}
+
+async fn on_command<'a>(invocation: &'a Invocation<'a>) -> Result<(), Error> {
+    if invocation.raw_args.is_empty() {
+        invocation.reply("Usage: !mod <name or search>");
+        return Ok(());
+    }
+    let query = invocation.raw_args;
+    match CACHE
+        .get_or_fetch(&query.to_lowercase(), || find_mod(query))
+        .await
+    {
+        Ok(info) => invocation.reply(info.describe()),
+        Err(e) => {
+            e.report(&format!("Could not look up mod {:?}", query));
+            match e {
+                Error::NotFound(_) => {
+                    invocation.reply(format!("Could not find a mod named {:?}", query))
+                }
+                _ => invocation.reply("Could not reach the mod portal, try again later"),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Look up the mod with the given name, or the first search result for `query`
+async fn find_mod(query: &str) -> Result<ModInfo, Error> {
+    if let Some(info) = load_mod(query).await? {
+        return Ok(info);
+    }
+    let mut url = url::Url::parse("https://mods.factorio.com/search")?;
+    url.query_pairs_mut().append_pair("query", query);
+    let html = http::get_text(url.as_str()).await?;
+    let name = SEARCH_RESULT_REGEX
+        .captures(&html)
+        .and_then(|captures| captures.get(1))
+        .ok_or_else(|| Error::NotFound(format!("No search results for {:?}", query)))?
+        .as_str();
+    let name = url::form_urlencoded::parse(format!("name={}", name).as_bytes())
+        .next()
+        .map(|(_, name)| name.into_owned())
+        .unwrap_or_default();
+    load_mod(&name)
+        .await?
+        .ok_or_else(|| Error::NotFound(format!("Search result {:?} does not exist", name)))
+}
+
+/// Load a single mod. Returns `None` if there is no mod with that name.
+async fn load_mod(name: &str) -> Result<Option<ModInfo>, Error> {
+    let mut url = url::Url::parse(API_URL)?;
+    url.path_segments_mut()
+        .map_err(|_| Error::Parse(String::from("Invalid api url")))?
+        .push(name);
+    http::get_json_if_found(url.as_str()).await
+}
+
+/// Load the latest release of every mod in `names` in a single request
+async fn load_mods(names: &[String]) -> Result<Vec<ModInfo>, Error> {
+    let mut url = url::Url::parse(API_URL)?;
+    {
+        let mut query = url.query_pairs_mut();
+        query.append_pair("page_size", "max");
+        for name in names {
+            query.append_pair("namelist", name);
+        }
+    }
+    let list: ModList = http::get_json(url.as_str()).await?;
+    Ok(list.results)
+}
+
+fn spawn(client: Client, channel: String, mods: Vec<String>) {
+    tokio::spawn(poll(client, channel, mods).in_current_span());
+}
+
+async fn poll(client: Client, channel: String, mods: Vec<String>) {
+    let server = client.server_config().host;
+    let interval = Duration::from_secs(WATCH_INTERVAL_SECONDS);
+    let channels = std::slice::from_ref(&channel);
+    client.wait_for_channels(channels).await;
+    while client.running() {
+        let delay = match load_mods(&mods).await {
+            Ok(_) if !client.running() => break,
+            Ok(infos) => {
+                for info in infos {
+                    let release = match info.latest_release() {
+                        Some(release) => release,
+                        None => continue,
+                    };
+                    let poller = format!("mod_portal {}", info.name);
+                    if last_seen::check(&server, &poller, &release.version) == Seen::New
+                        && client.in_channels(channels)
+                        && client
+                            .send_to_channel(
+                                &channel,
+                                format!("New release of {}", info.describe()),
+                            )
+                            .wait()
+                            .await
+                    {
+                        last_seen::mark(&server, &poller, &release.version);
+                    }
+                }
+                interval
+            }
+            Err(e) => {
+                e.report("Cannot poll the mod portal");
+                e.retry_delay(interval)
+            }
+        };
+        tokio::time::sleep(delay).await;
+    }
+}
+
+#[tokio::test]
+async fn test_find_mod() {
+    let info = find_mod("space-exploration")
+        .await
+        .expect("Could not load mod");
+    println!("{}", info.describe());
+}
+
+#[test]
+fn test_describe() {
+    let info: ModInfo = serde_json::from_str(
+        r#"{
+            "name": "Even Distribution", "title": "Even Distribution", "owner": "Mylon",
+            "downloads_count": 1234, "summary": "Distributes items",
+            "releases": [
+                {"version": "1.0.10", "info_json": {"factorio_version": "1.1"}, "released_at": "2021-01-01T00:00:00Z"},
+                {"version": "2.0.1", "info_json": {"factorio_version": "2.0"}, "released_at": "2024-10-21T00:00:00Z"}
+            ]
+        }"#,
+    )
+    .expect("Could not parse mod");
+    assert_eq!(
+        info.describe(),
+        "Even Distribution by Mylon: v2.0.1 for Factorio 2.0, 1234 downloads https://mods.factorio.com/mod/Even%20Distribution"
+    );
+
+    let list: ModList = serde_json::from_str(
+        r#"{"pagination": null, "results": [{
+            "name": "jetpack", "title": "Jetpack", "owner": "Earendel", "downloads_count": 5,
+            "latest_release": {"version": "0.4.1", "info_json": {"factorio_version": "2.0"}, "released_at": "2024-11-01T00:00:00Z"}
+        }]}"#,
+    )
+    .expect("Could not parse mod list");
+    assert_eq!(
+        list.results[0].latest_release().map(|r| r.version.as_str()),
+        Some("0.4.1")
+    );
+}
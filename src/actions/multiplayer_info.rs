@@ -1,39 +1,182 @@
-use crate::data::Message;
+//! Multiplayer info
+//!
+//! Searches the public multiplayer games on the Factorio matchmaking server:
+//! - `!mp`: the 5 games with the most players
+//! - `!mp <search> [--version <version>] [--vanilla|--modded] [--password|--no-password]`: the
+//!   games whose name, description or tags contain every word of the search, filtered by the flags
+//! - `!mp player <name>`: the game a player is currently playing on
+
+use super::{Action, CommandInfo, Invocation};
+use crate::error::Error;
+use futures::future::BoxFuture;
 use std::fmt::Write;
 
-pub async fn on_message<'a>(message: &'a Message<'a>) -> Result<(), String> {
-    if message.body.trim() == "!mp" {
-        let mut games = match load_games(message.config).await {
-            Ok(games) => games,
+const USAGE: &str = "Usage: !mp [search] [--version <version>] [--vanilla|--modded] [--password|--no-password], !mp player <name>";
+
+pub struct MultiplayerInfo;
+
+impl Action for MultiplayerInfo {
+    fn name(&self) -> &'static str {
+        "multiplayer_info"
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        &[CommandInfo {
+            name: "mp",
+            aliases: &["multiplayer"],
+            help: "Searches the public multiplayer games. Usage: !mp [search] [--version <version>] [--vanilla|--modded] [--password|--no-password], !mp player <name>",
+        }]
+    }
+
+    fn on_command<'a>(
+        &'a self,
+        invocation: &'a Invocation<'a>,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(on_command(invocation))
+    }
+}
+
+async fn on_command<'a>(invocation: &'a Invocation<'a>) -> Result<(), Error> {
+    let query = match invocation.args.split_first() {
+        Some((first, rest)) if first.eq_ignore_ascii_case("player") => match rest {
+            [player] => Query::Player(player),
+            _ => {
+                invocation.reply("Usage: !mp player <name>");
+                return Ok(());
+            }
+        },
+        _ => match Filter::parse(&invocation.args) {
+            Ok(filter) => Query::Search(filter),
             Err(e) => {
-                eprintln!("Could not load games: {:?}", e);
-                message.reply("Error loading games");
+                invocation.reply(format!("{}. {}", e, USAGE));
                 return Ok(());
             }
-        };
+        },
+    };
+
+    let mut games = match load_games(invocation.config).await {
+        Ok(games) => games,
+        Err(e) => {
+            e.report("Could not load games");
+            invocation.reply("Error loading games");
+            return Ok(());
+        }
+    };
+    games.sort_unstable_by_key(|g| (usize::MAX - g.players.len(), g.game_id));
 
-        games.sort_unstable_by_key(|g| usize::max_value() - g.players.len());
-        let mut response = String::from("Top 5 games: ");
-        for (index, game) in games.iter().take(5).enumerate() {
-            if index > 0 {
-                response += ", ";
+    match query {
+        Query::Player(player) => {
+            let game = games
+                .iter()
+                .find(|g| g.players.iter().any(|p| p.eq_ignore_ascii_case(player)));
+            match game {
+                Some(game) => {
+                    invocation.reply(format!("{} is playing on {}", player, describe(game)))
+                }
+                None => invocation.reply(format!("{} is not playing on a public game", player)),
             }
-            write!(
-                &mut response,
-                "{} ({} players, v{}{}{})",
-                game.name,
-                game.players.len(),
-                game.application_version.game_version,
-                if game.has_password { " +p" } else { "" },
-                if game.mod_count > 0 { " modded" } else { "" }
-            )
-            .expect("Could not append server text to string");
         }
-        message.reply(&response);
+        Query::Search(filter) => {
+            let matching = games
+                .iter()
+                .filter(|g| filter.matches(g))
+                .collect::<Vec<_>>();
+            if matching.is_empty() {
+                invocation.reply("No games found");
+                return Ok(());
+            }
+            let mut response = if filter.is_empty() {
+                String::from("Top 5 games: ")
+            } else {
+                format!("{} games found, top 5: ", matching.len())
+            };
+            for (index, game) in matching.iter().take(5).enumerate() {
+                if index > 0 {
+                    response += ", ";
+                }
+                response += &describe(game);
+            }
+            invocation.reply(&response);
+        }
     }
     Ok(())
 }
 
+enum Query<'a> {
+    Search(Filter),
+    Player(&'a str),
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Filter {
+    /// Lowercased words that all have to appear in the name, description or tags
+    words: Vec<String>,
+    /// A version prefix, e.g. `1.1` matches `1.1.110`
+    version: Option<String>,
+    modded: Option<bool>,
+    password: Option<bool>,
+}
+
+impl Filter {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut filter = Filter::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--version" => {
+                    let version = args.next().ok_or("--version needs a version")?;
+                    filter.version = Some(version.trim_start_matches('v').to_owned());
+                }
+                "--vanilla" => filter.modded = Some(false),
+                "--modded" => filter.modded = Some(true),
+                "--password" => filter.password = Some(true),
+                "--no-password" => filter.password = Some(false),
+                flag if flag.starts_with("--") => return Err(format!("Unknown flag {}", flag)),
+                word => filter.words.push(word.to_lowercase()),
+            }
+        }
+        Ok(filter)
+    }
+
+    fn is_empty(&self) -> bool {
+        *self == Filter::default()
+    }
+
+    fn matches(&self, game: &GameInfo) -> bool {
+        let text =
+            format!("{} {} {}", game.name, game.description, game.tags.join(" ")).to_lowercase();
+        let game_version = &game.application_version.game_version;
+        self.words.iter().all(|word| text.contains(word.as_str()))
+            && self.version.as_ref().is_none_or(|version| {
+                game_version == version || game_version.starts_with(&format!("{}.", version))
+            })
+            && self
+                .modded
+                .is_none_or(|modded| modded == (game.mod_count > 0))
+            && self
+                .password
+                .is_none_or(|password| password == game.has_password)
+    }
+}
+
+fn describe(game: &GameInfo) -> String {
+    let mut description = format!("{} ({}", game.name, game.players.len());
+    if game.max_players > 0 {
+        write!(&mut description, "/{}", game.max_players).expect("Could not write to string");
+    }
+    write!(
+        &mut description,
+        " players, v{}, {}h{:02}m{}{})",
+        game.application_version.game_version,
+        game.game_time_elapsed / 60,
+        game.game_time_elapsed % 60,
+        if game.has_password { " +p" } else { "" },
+        if game.mod_count > 0 { " modded" } else { "" }
+    )
+    .expect("Could not write to string");
+    description
+}
+
 #[tokio::test]
 pub async fn test() {
     if let Ok(config) = crate::Config::from_file("config.json") {
@@ -42,44 +185,40 @@ pub async fn test() {
     }
 }
 
-async fn load_games(config: &crate::Config) -> Result<Vec<GameInfo>, String> {
-    reqwest::get(&format!(
+async fn load_games(config: &crate::Config) -> Result<Vec<GameInfo>, Error> {
+    crate::http::get_json(&format!(
         "https://multiplayer.factorio.com/get-games?username={}&token={}",
         config.factorio_username, config.factorio_key
     ))
     .await
-    .map_err(|e| e.to_string())?
-    .json()
-    .await
-    .map_err(|e| e.to_string())
 }
 
 #[derive(Debug, Deserialize)]
 struct GameInfo {
-    // pub game_id: u64,
+    /// Breaks ties between games with the same amount of players, so results don't jump around
+    #[serde(default, deserialize_with = "string_or_number_to_u64")]
+    pub game_id: u64,
     pub name: String,
-    // #[serde(deserialize_with = "string_or_number_to_u64")]
-    // pub max_players: u64,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default, deserialize_with = "string_or_number_to_u64")]
+    pub max_players: u64,
     #[serde(default)]
     pub players: Vec<String>,
     pub application_version: GameVersion,
-    // #[serde(deserialize_with = "string_or_number_to_u64")]
-    // pub game_time_elapsed: u64,
+    /// In minutes
+    #[serde(default, deserialize_with = "string_or_number_to_u64")]
+    pub game_time_elapsed: u64,
     #[serde(deserialize_with = "parse_string_bool")]
     pub has_password: bool,
-    // #[serde(default)]
-    // pub server_id: Option<String>,
-    // pub has_mods: bool,
+    #[serde(default)]
+    pub tags: Vec<String>,
     pub mod_count: u64,
 }
 
 #[derive(Debug, Deserialize)]
 struct GameVersion {
     pub game_version: String,
-    // #[serde(deserialize_with = "string_or_number_to_u64")]
-    // pub build_version: u64,
-    // pub build_mode: String,
-    // pub platform: String,
 }
 
 fn parse_string_bool<'de, D>(deserializer: D) -> std::result::Result<bool, D::Error>
@@ -113,33 +252,72 @@ where
     deserializer.deserialize_any(IdVisitor)
 }
 
-// fn string_or_number_to_u64<'de, D>(deserializer: D) -> std::result::Result<u64, D::Error>
-// where
-//     D: serde::Deserializer<'de>,
-// {
-//     struct IdVisitor;
-
-//     impl<'de> serde::de::Visitor<'de> for IdVisitor {
-//         type Value = u64;
-
-//         fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-//             f.write_str("user ID as a number or string")
-//         }
-
-//         fn visit_u64<E>(self, id: u64) -> std::result::Result<Self::Value, E>
-//         where
-//             E: serde::de::Error,
-//         {
-//             Ok(id)
-//         }
-
-//         fn visit_str<E>(self, id: &str) -> std::result::Result<Self::Value, E>
-//         where
-//             E: serde::de::Error,
-//         {
-//             id.parse().map_err(serde::de::Error::custom)
-//         }
-//     }
-
-//     deserializer.deserialize_any(IdVisitor)
-// }
+fn string_or_number_to_u64<'de, D>(deserializer: D) -> std::result::Result<u64, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    struct IdVisitor;
+
+    impl<'de> serde::de::Visitor<'de> for IdVisitor {
+        type Value = u64;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("user ID as a number or string")
+        }
+
+        fn visit_u64<E>(self, id: u64) -> std::result::Result<Self::Value, E>
+        where
+            E: serde::de::Error,
+        {
+            Ok(id)
+        }
+
+        fn visit_str<E>(self, id: &str) -> std::result::Result<Self::Value, E>
+        where
+            E: serde::de::Error,
+        {
+            id.parse().map_err(serde::de::Error::custom)
+        }
+    }
+
+    deserializer.deserialize_any(IdVisitor)
+}
+
+#[test]
+fn test_filter() {
+    let games: Vec<GameInfo> = serde_json::from_str(
+        r#"[{
+            "game_id": 1, "name": "Space Exploration", "description": "A long run",
+            "max_players": "0", "players": ["Trangar"], "game_time_elapsed": 125,
+            "application_version": {"game_version": "1.1.110", "build_version": 62614},
+            "has_password": "false", "server_id": "abc", "tags": ["[SE]", "English"], "mod_count": 80
+        }, {
+            "game_id": "2", "name": "Vanilla", "application_version": {"game_version": "1.10.1"},
+            "has_password": true, "mod_count": 0
+        }]"#,
+    )
+    .expect("Could not parse games");
+    let args = |text: &str| text.split(' ').map(String::from).collect::<Vec<_>>();
+    let matching = |text: &str| {
+        let filter = Filter::parse(&args(text)).expect("Could not parse filter");
+        games
+            .iter()
+            .filter(|g| filter.matches(g))
+            .map(|g| g.game_id)
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(matching("space run"), vec![1]);
+    assert_eq!(matching("english"), vec![1]);
+    assert_eq!(matching("--version 1.1"), vec![1]);
+    assert_eq!(matching("--version v1.10"), vec![2]);
+    assert_eq!(matching("--vanilla"), vec![2]);
+    assert_eq!(matching("--modded --no-password"), vec![1]);
+    assert_eq!(matching("--password space"), Vec::<u64>::new());
+    assert!(Filter::parse(&args("--version")).is_err());
+    assert!(Filter::parse(&args("--unknown")).is_err());
+    assert_eq!(
+        describe(&games[0]),
+        "Space Exploration (1 players, v1.1.110, 2h05m modded)"
+    );
+}
@@ -0,0 +1,66 @@
+//! Status
+//!
+//! `!status` shows which host the bot is connected to, for how long, and how often it had to
+//! reconnect.
+
+use super::{Action, CommandInfo, Invocation};
+use crate::error::Error;
+use futures::future::BoxFuture;
+
+pub struct Status;
+
+impl Action for Status {
+    fn name(&self) -> &'static str {
+        "status"
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        &[CommandInfo {
+            name: "status",
+            aliases: &["uptime"],
+            help: "Shows how long the bot has been connected, and how often it reconnected",
+        }]
+    }
+
+    fn on_command<'a>(
+        &'a self,
+        invocation: &'a Invocation<'a>,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(on_command(invocation))
+    }
+}
+
+async fn on_command<'a>(invocation: &'a Invocation<'a>) -> Result<(), Error> {
+    let connection = invocation.client.connection();
+    invocation.reply(format!(
+        "Connected to {} for {}, reconnected {} times since starting",
+        connection.host,
+        format_duration(
+            connection
+                .connected_at
+                .map_or(0, |connected_at| connected_at.elapsed().as_secs())
+        ),
+        connection.reconnects
+    ));
+    Ok(())
+}
+
+fn format_duration(seconds: u64) -> String {
+    let (days, hours, minutes) = (
+        seconds / (60 * 60 * 24),
+        seconds / (60 * 60) % 24,
+        seconds / 60 % 60,
+    );
+    match (days, hours) {
+        (0, 0) => format!("{}m", minutes),
+        (0, _) => format!("{}h {}m", hours, minutes),
+        _ => format!("{}d {}h {}m", days, hours, minutes),
+    }
+}
+
+#[test]
+fn test_format_duration() {
+    assert_eq!(format_duration(59), "0m");
+    assert_eq!(format_duration(60 * 61), "1h 1m");
+    assert_eq!(format_duration(60 * 60 * 24 * 2 + 60 * 5), "2d 0h 5m");
+}
@@ -0,0 +1,96 @@
+//! Templates
+//!
+//! Learned commands can contain variables in their response, which are filled in when the command
+//! is used:
+//! - `{nick}`: the user that used the command
+//! - `{args}`: all text after the trigger
+//! - `{1}`, `{2}`, ...: a single argument after the trigger
+//! - `{channel}`: the channel the command was used in
+//! - `{topic}`: the topic of that channel
+//! - `{random:a|b|c}`: one of the given options, picked at random
+//!
+//! Anything else between braces is left as it is.
+
+use rand::seq::SliceRandom;
+
+pub struct Context<'a> {
+    pub nick: &'a str,
+    pub args: &'a str,
+    pub arg_list: &'a [String],
+    pub channel: &'a str,
+    pub topic: &'a str,
+}
+
+pub fn render(template: &str, context: &Context) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut remaining = template;
+
+    while let Some(start) = remaining.find('{') {
+        let after_brace = &remaining[start + 1..];
+        let end = match after_brace.find('}') {
+            Some(end) => end,
+            None => break,
+        };
+        result += &remaining[..start];
+        let variable = &after_brace[..end];
+        match expand(variable, context) {
+            Some(value) => result += &value,
+            None => {
+                result.push('{');
+                result += variable;
+                result.push('}');
+            }
+        }
+        remaining = &after_brace[end + 1..];
+    }
+    result += remaining;
+    result
+}
+
+fn expand(variable: &str, context: &Context) -> Option<String> {
+    if let Some(options) = variable.strip_prefix("random:") {
+        let options = options.split('|').collect::<Vec<_>>();
+        return options
+            .choose(&mut rand::thread_rng())
+            .map(|option| option.trim().to_owned());
+    }
+    if let Ok(index) = variable.parse::<usize>() {
+        return Some(
+            index
+                .checked_sub(1)
+                .and_then(|index| context.arg_list.get(index))
+                .cloned()
+                .unwrap_or_default(),
+        );
+    }
+    match variable {
+        "nick" => Some(context.nick.to_owned()),
+        "args" => Some(context.args.to_owned()),
+        "channel" => Some(context.channel.to_owned()),
+        "topic" => Some(context.topic.to_owned()),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_render() {
+    let arg_list = vec![String::from("Trangar"), String::from("fish")];
+    let context = Context {
+        nick: "Someone",
+        args: "Trangar fish",
+        arg_list: &arg_list,
+        channel: "#factorio",
+        topic: "Welcome",
+    };
+    assert_eq!(
+        render("{nick} slaps {1} with a {2}{3}", &context),
+        "Someone slaps Trangar with a fish"
+    );
+    assert_eq!(
+        render("{channel}: {topic} ({args})", &context),
+        "#factorio: Welcome (Trangar fish)"
+    );
+    assert_eq!(render("{unknown} {} {nick", &context), "{unknown} {} {nick");
+    assert_eq!(render("{random:a}", &context), "a");
+    assert!(["a", "b"].contains(&render("{random:a|b}", &context).as_str()));
+}
@@ -0,0 +1,73 @@
+//! Topic
+//!
+//! Lets channel ops change a single named segment of the channel topic, without having to retype
+//! the rest of it:
+//! - `!topic set <segment> <text>`
+//! - `!topic segments`
+
+use super::{Action, CommandInfo, Invocation};
+use crate::error::Error;
+use futures::future::BoxFuture;
+
+pub struct Topic;
+
+impl Action for Topic {
+    fn name(&self) -> &'static str {
+        "topic"
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        &[CommandInfo {
+            name: "topic",
+            aliases: &[],
+            help: "Changes a part of the channel topic. Usage: !topic set <segment> <text>, !topic segments",
+        }]
+    }
+
+    fn on_command<'a>(
+        &'a self,
+        invocation: &'a Invocation<'a>,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(on_command(invocation))
+    }
+}
+
+async fn on_command<'a>(invocation: &'a Invocation<'a>) -> Result<(), Error> {
+    let channel = match invocation.channel.as_ref() {
+        Some(channel) if invocation.sender_is_op() => channel,
+        _ => return Ok(()),
+    };
+    let segments = channel.topic_segments();
+    if segments.is_empty() {
+        invocation.reply(format!("{} has no topic segments", channel.name()));
+        return Ok(());
+    }
+
+    let (subcommand, rest) = split_first_word(invocation.raw_args);
+    match subcommand {
+        "set" => {
+            let (segment, text) = split_first_word(rest);
+            if segment.is_empty() || text.is_empty() {
+                invocation.reply("Usage: !topic set <segment> <text>");
+            } else if let Err(e) =
+                invocation
+                    .client
+                    .set_topic_segment(&channel.name(), segment, text.to_owned())
+            {
+                invocation.reply(format!("{}, segments are: {}", e, segments.join(", ")));
+            }
+        }
+        "segments" | "" => {
+            invocation.reply(format!("Topic segments: {}", segments.join(", ")));
+        }
+        _ => invocation.reply("Usage: !topic set <segment> <text>, !topic segments"),
+    }
+    Ok(())
+}
+
+fn split_first_word(text: &str) -> (&str, &str) {
+    match text.split_once(char::is_whitespace) {
+        Some((first, rest)) => (first, rest.trim()),
+        None => (text, ""),
+    }
+}
@@ -0,0 +1,239 @@
+//! Wiki
+//!
+//! `!wiki <term>` looks up a page on the Factorio wiki and replies with the first sentence of the
+//! page. If there is no page with that exact title, the best search result is used instead, or the
+//! spelling suggestion of the wiki if there are no results at all.
+
+use super::{Action, CommandInfo, Invocation};
+use crate::{error::Error, http};
+use futures::future::BoxFuture;
+use regex::Regex;
+use serde_json::Value;
+
+const API_URL: &str = "https://wiki.factorio.com/api.php";
+const PAGE_URL: &str = "https://wiki.factorio.com/";
+/// The maximum amount of bytes of the page summary
+const MAX_SUMMARY_LENGTH: usize = 300;
+
+lazy_static::lazy_static! {
+    static ref PARAGRAPH_REGEX: Regex =
+        Regex::new(r"(?s)<p>(.*?)</p>").expect("Could not compile paragraph regex");
+    static ref TAG_REGEX: Regex = Regex::new(r"<[^>]*>").expect("Could not compile tag regex");
+}
+
+pub struct Wiki;
+
+impl Action for Wiki {
+    fn name(&self) -> &'static str {
+        "wiki"
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        &[CommandInfo {
+            name: "wiki",
+            aliases: &[],
+            help: "Looks up a page on the Factorio wiki. Usage: !wiki <term>",
+        }]
+    }
+
+    fn on_command<'a>(
+        &'a self,
+        invocation: &'a Invocation<'a>,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(on_command(invocation))
+    }
+}
+
+async fn on_command<'a>(invocation: &'a Invocation<'a>) -> Result<(), Error> {
+    if invocation.raw_args.is_empty() {
+        invocation.reply("Usage: !wiki <term>");
+        return Ok(());
+    }
+    match query_wiki(invocation.raw_args).await {
+        Ok(result) => invocation.reply(result),
+        Err(e) => {
+            e.report("Could not query the wiki");
+            invocation.reply(format!("Could not query the wiki: {}", e));
+        }
+    }
+    Ok(())
+}
+
+pub async fn query_wiki(term: &str) -> Result<String, Error> {
+    if let Some(page) = load_page(term).await? {
+        return Ok(page.describe());
+    }
+
+    let json: Value = http::get_json(&api_url(&[
+        ("action", "query"),
+        ("list", "search"),
+        ("srsearch", term),
+        ("srlimit", "1"),
+        ("srinfo", "suggestion"),
+        ("srprop", ""),
+    ])?)
+    .await?;
+    let first_result = json
+        .pointer("/query/search/0/title")
+        .and_then(Value::as_str);
+    let suggestion = json
+        .pointer("/query/searchinfo/suggestion")
+        .and_then(Value::as_str);
+
+    match (first_result, suggestion) {
+        (Some(title), _) => match load_page(title).await? {
+            Some(page) => Ok(format!("Did you mean {}? {}", page.title, page.describe())),
+            None => Err(Error::NotFound(format!(
+                "Search result {:?} does not exist",
+                title
+            ))),
+        },
+        (None, Some(suggestion)) => Ok(format!(
+            "Nothing found for {:?}, did you mean {:?}?",
+            term, suggestion
+        )),
+        (None, None) => Ok(format!("Nothing found for {:?}", term)),
+    }
+}
+
+struct Page {
+    title: String,
+    summary: String,
+}
+
+impl Page {
+    fn url(&self) -> String {
+        let mut url = url::Url::parse(PAGE_URL).expect("Invalid wiki url");
+        url.path_segments_mut()
+            .expect("Invalid wiki url")
+            .pop_if_empty()
+            .push(&self.title.replace(' ', "_"));
+        url.to_string()
+    }
+
+    fn describe(&self) -> String {
+        if self.summary.is_empty() {
+            format!("{}: {}", self.title, self.url())
+        } else {
+            format!("{}: {} {}", self.title, self.summary, self.url())
+        }
+    }
+}
+
+/// Load the introduction of the page with the given title, following redirects. Returns `None` if
+/// the page does not exist.
+async fn load_page(title: &str) -> Result<Option<Page>, Error> {
+    let json: Value = http::get_json(&api_url(&[
+        ("action", "parse"),
+        ("page", title),
+        ("prop", "text"),
+        ("section", "0"),
+        ("redirects", "1"),
+        ("disablelimitreport", "1"),
+    ])?)
+    .await?;
+
+    if let Some(error) = json.get("error") {
+        return match error.get("code").and_then(Value::as_str) {
+            Some("missingtitle") | Some("invalidtitle") => Ok(None),
+            _ => Err(Error::Parse(format!("Wiki error: {}", error))),
+        };
+    }
+    let title = json
+        .pointer("/parse/title")
+        .and_then(Value::as_str)
+        .ok_or_else(|| Error::Parse(String::from("Wiki response has no title")))?;
+    let html = json
+        .pointer("/parse/text")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    Ok(Some(Page {
+        title: title.to_owned(),
+        summary: summarize(html),
+    }))
+}
+
+fn api_url(params: &[(&str, &str)]) -> Result<String, Error> {
+    let mut url = url::Url::parse(API_URL)?;
+    url.query_pairs_mut()
+        .extend_pairs(params)
+        .append_pair("format", "json")
+        .append_pair("formatversion", "2");
+    Ok(url.to_string())
+}
+
+/// The first sentence of the first paragraph of text outside of a table, e.g. an infobox
+fn summarize(html: &str) -> String {
+    let html = remove_tables(html);
+    let paragraph = PARAGRAPH_REGEX
+        .captures_iter(&html)
+        .map(|captures| http::unescape_html(TAG_REGEX.replace_all(&captures[1], "").trim()))
+        .find(|text| !text.is_empty())
+        .unwrap_or_default();
+    let paragraph = paragraph.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    let sentence = match paragraph.find(". ") {
+        Some(end) => &paragraph[..=end],
+        None => &paragraph,
+    };
+    http::shorten(sentence, MAX_SUMMARY_LENGTH)
+}
+
+/// Remove every `<table>`, including tables nested inside of it
+fn remove_tables(html: &str) -> String {
+    let mut result = String::with_capacity(html.len());
+    let mut depth = 0usize;
+    let mut remaining = html;
+    loop {
+        let open = remaining.find("<table");
+        let close = remaining.find("</table>");
+        match (open, close) {
+            (Some(open), close) if close.is_none_or(|close| open < close) => {
+                if depth == 0 {
+                    result += &remaining[..open];
+                }
+                depth += 1;
+                remaining = &remaining[open + "<table".len()..];
+            }
+            (_, Some(close)) => {
+                if depth == 0 {
+                    result += &remaining[..close];
+                }
+                depth = depth.saturating_sub(1);
+                remaining = &remaining[close + "</table>".len()..];
+            }
+            (_, None) => {
+                if depth == 0 {
+                    result += remaining;
+                }
+                return result;
+            }
+        }
+    }
+}
+
+#[tokio::test]
+pub async fn test() {
+    let response = query_wiki("iron plate")
+        .await
+        .expect("Could not query the wiki");
+    println!("{}", response);
+}
+
+#[test]
+fn test_summarize() {
+    let html = r#"<div class="mw-parser-output"><table class="infobox"><tr><td>
+        <table><tr><td><p>Recipe</p></td></tr></table><p>Stack size</p></td></tr></table>
+        <p><b>Iron plates</b> are one of the
+        <a href="/Intermediate_products">intermediate products</a> &amp; are made by smelting iron ore. They are used everywhere.</p></div>"#;
+    assert_eq!(
+        summarize(html),
+        "Iron plates are one of the intermediate products & are made by smelting iron ore."
+    );
+    assert_eq!(summarize("<p></p>"), "");
+
+    let long = format!("<p>{}</p>", "word ".repeat(100));
+    let summary = summarize(&long);
+    assert!(summary.len() <= MAX_SUMMARY_LENGTH + 3);
+    assert!(summary.ends_with("word..."));
+}
@@ -2,37 +2,58 @@
 //!
 //! Queries wolfram alpha with the given text
 
-use crate::data::Message;
+use super::{Action, CommandInfo, Invocation};
+use crate::error::Error;
+use futures::future::BoxFuture;
 use serde_json::Value;
+use tracing::debug;
 
-pub async fn on_message<'a>(message: &'a Message<'a>) -> Result<(), String> {
-    if message.body.starts_with("!wa") {
-        let query = &message.body["!wa".len() + 1..].trim();
-        match query_wolfram_alpha(message.config, query).await {
-            Ok(result) => message.reply(&result),
-            Err(e) => {
-                eprintln!("Could not query WA: {:?}", e);
-                message.reply(&format!("Could not query WA: {:?}", e));
-            }
+pub struct WolframAlpha;
+
+impl Action for WolframAlpha {
+    fn name(&self) -> &'static str {
+        "wolfram_alpha"
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        &[CommandInfo {
+            name: "wa",
+            aliases: &["wolfram"],
+            help: "Asks Wolfram Alpha. Usage: !wa <query>",
+        }]
+    }
+
+    fn on_command<'a>(
+        &'a self,
+        invocation: &'a Invocation<'a>,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(on_command(invocation))
+    }
+}
+
+async fn on_command<'a>(invocation: &'a Invocation<'a>) -> Result<(), Error> {
+    if invocation.raw_args.is_empty() {
+        invocation.reply("Usage: !wa <query>");
+        return Ok(());
+    }
+    match query_wolfram_alpha(invocation.config, invocation.raw_args).await {
+        Ok(result) => invocation.reply(&result),
+        Err(e) => {
+            e.report("Could not query WA");
+            invocation.reply(format!("Could not query WA: {}", e));
         }
     }
     Ok(())
 }
 
-pub async fn query_wolfram_alpha(config: &crate::Config, query: &str) -> Result<String, String> {
-    let mut url =
-        url::Url::parse("https://api.wolframalpha.com/v2/query").map_err(|e| e.to_string())?;
+pub async fn query_wolfram_alpha(config: &crate::Config, query: &str) -> Result<String, Error> {
+    let mut url = url::Url::parse("https://api.wolframalpha.com/v2/query")?;
     url.query_pairs_mut()
         .append_pair("input", query)
         .append_pair("appid", &config.wolframalpha)
         .append_pair("output", "json");
 
-    let json: serde_json::Value = reqwest::get(url.as_str())
-        .await
-        .map_err(|e| e.to_string())?
-        .json()
-        .await
-        .map_err(|e| e.to_string())?;
+    let json: serde_json::Value = crate::http::get_json(url.as_str()).await?;
 
     Ok(
         if let Some(Value::Array(pods)) = json.pointer("/queryresult/pods") {
@@ -46,10 +67,10 @@ pub async fn query_wolfram_alpha(config: &crate::Config, query: &str) -> Result<
                 }
                 if let Some(arr) = primary_pod["subpods"].as_array() {
                     for subpod in arr {
-                        if let Some(&Value::String(ref title)) = subpod.get("title") {
+                        if let Some(Value::String(title)) = subpod.get("title") {
                             result.push(title);
                         }
-                        if let Some(&Value::String(ref plaintext)) = subpod.get("plaintext") {
+                        if let Some(Value::String(plaintext)) = subpod.get("plaintext") {
                             result.push(plaintext);
                         }
                     }
@@ -74,7 +95,7 @@ pub async fn query_wolfram_alpha(config: &crate::Config, query: &str) -> Result<
         } else if let Some(didyoumean) = json.pointer("/queryresults/didyoumeans") {
             format!("Didyoumeans: {:?}", didyoumean)
         } else {
-            println!("{}", serde_json::to_string_pretty(&json).unwrap());
+            debug!(%json, "Did not find a WA result");
             String::from("Did not find WA result, Trangar has been pinged to fix this")
         },
     )
@@ -0,0 +1,269 @@
+//! YouTube
+//!
+//! Replies to YouTube links posted in a channel with the title, duration and uploader of the
+//! video.
+//!
+//! New uploads of the channels in `youtube_channels` are announced in the configured IRC channels.
+//!
+//! Both use the YouTube Data api with the `youtube_key` from config.json, and do nothing if it is
+//! not set.
+
+use super::{
+    last_seen::{self, Seen},
+    Action,
+};
+use crate::{
+    data::{Client, Message},
+    error::Error,
+    http,
+};
+use futures::future::{self, BoxFuture};
+use regex::Regex;
+use serde_json::Value;
+use std::time::Duration;
+use tracing::Instrument;
+
+const API_URL: &str = "https://www.googleapis.com/youtube/v3/";
+const WATCH_INTERVAL_SECONDS: u64 = 60 * 15;
+/// Links in a single message that are looked up, any more are ignored
+const MAX_LINKS_PER_MESSAGE: usize = 3;
+
+lazy_static::lazy_static! {
+    static ref VIDEO_LINK_REGEX: Regex = Regex::new(
+        r"(?:youtube\.com/(?:watch\?(?:\S*&)?v=|shorts/|live/)|youtu\.be/)([A-Za-z0-9_-]{11})"
+    )
+    .expect("Could not compile video link regex");
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct YoutubeChannelConfig {
+    /// Used in the announcement and to identify this channel in `persist/last_seen.json`
+    pub name: String,
+    /// The id of the YouTube channel, starting with `UC`
+    pub channel_id: String,
+    /// The IRC channels new uploads are announced in
+    pub channels: Vec<String>,
+}
+
+struct Video {
+    title: String,
+    uploader: String,
+    duration: String,
+}
+
+pub struct Youtube;
+
+impl Action for Youtube {
+    fn name(&self) -> &'static str {
+        "youtube"
+    }
+
+    fn on_start(&self, client: &Client) -> Result<(), Error> {
+        let key = client.config().youtube_key.clone();
+        if key.is_empty() {
+            return Ok(());
+        }
+        for channel in client.server_config().youtube_channels {
+            spawn(client.clone(), key.clone(), channel);
+        }
+        Ok(())
+    }
+
+    fn on_message<'a>(&'a self, message: &'a Message<'a>) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(on_message(message))
+    }
+}
+
+async fn on_message<'a>(message: &'a Message<'a>) -> Result<(), Error> {
+    if message.config.youtube_key.is_empty() || message.channel.is_none() {
+        return Ok(());
+    }
+    let mut ids = video_ids(message.body);
+    ids.truncate(MAX_LINKS_PER_MESSAGE);
+    if ids.is_empty() {
+        return Ok(());
+    }
+
+    for video in load_videos(&message.config.youtube_key, &ids).await? {
+        message.reply(format!(
+            "YouTube: {} [{}] by {}",
+            video.title, video.duration, video.uploader
+        ));
+    }
+    Ok(())
+}
+
+/// The ids of every YouTube video linked in `text`, without duplicates
+fn video_ids(text: &str) -> Vec<String> {
+    let mut ids = Vec::new();
+    for captures in VIDEO_LINK_REGEX.captures_iter(text) {
+        let id = captures[1].to_owned();
+        if !ids.contains(&id) {
+            ids.push(id);
+        }
+    }
+    ids
+}
+
+fn api_url(endpoint: &str, key: &str, params: &[(&str, &str)]) -> Result<String, Error> {
+    let mut url = url::Url::parse(API_URL).and_then(|url| url.join(endpoint))?;
+    url.query_pairs_mut()
+        .extend_pairs(params)
+        .append_pair("key", key);
+    Ok(url.to_string())
+}
+
+async fn load_videos(key: &str, ids: &[String]) -> Result<Vec<Video>, Error> {
+    let json: Value = http::get_json(&api_url(
+        "videos",
+        key,
+        &[("part", "snippet,contentDetails"), ("id", &ids.join(","))],
+    )?)
+    .await?;
+    let items = json
+        .get("items")
+        .and_then(Value::as_array)
+        .ok_or_else(|| Error::Parse(String::from("YouTube response has no items")))?;
+    Ok(items
+        .iter()
+        .map(|item| {
+            let get = |pointer: &str| {
+                item.pointer(pointer)
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .to_owned()
+            };
+            Video {
+                title: get("/snippet/title"),
+                uploader: get("/snippet/channelTitle"),
+                duration: format_duration(&get("/contentDetails/duration")),
+            }
+        })
+        .collect())
+}
+
+/// Load the id and title of the latest upload of a YouTube channel
+async fn latest_upload(key: &str, channel_id: &str) -> Result<(String, String), Error> {
+    // The playlist with all uploads of a channel has the same id as the channel, but with UU
+    // instead of UC
+    let playlist_id = match channel_id.strip_prefix("UC") {
+        Some(id) => format!("UU{}", id),
+        None => {
+            return Err(Error::Config(format!(
+                "Invalid channel id {:?}",
+                channel_id
+            )))
+        }
+    };
+    let json: Value = http::get_json(&api_url(
+        "playlistItems",
+        key,
+        &[
+            ("part", "snippet"),
+            ("playlistId", &playlist_id),
+            ("maxResults", "1"),
+        ],
+    )?)
+    .await?;
+    let snippet = json
+        .pointer("/items/0/snippet")
+        .ok_or_else(|| Error::NotFound(String::from("The channel has no uploads")))?;
+    let get = |pointer: &str| {
+        snippet
+            .pointer(pointer)
+            .and_then(Value::as_str)
+            .map(str::to_owned)
+            .ok_or_else(|| Error::Parse(format!("Upload has no {}", pointer)))
+    };
+    Ok((get("/resourceId/videoId")?, get("/title")?))
+}
+
+fn spawn(client: Client, key: String, channel: YoutubeChannelConfig) {
+    tokio::spawn(poll(client, key, channel).in_current_span());
+}
+
+async fn poll(client: Client, key: String, channel: YoutubeChannelConfig) {
+    let server = client.server_config().host;
+    let poller = format!("youtube {}", channel.channel_id);
+    let interval = Duration::from_secs(WATCH_INTERVAL_SECONDS);
+    client.wait_for_channels(&channel.channels).await;
+    while client.running() {
+        let delay = match latest_upload(&key, &channel.channel_id).await {
+            Ok(_) if !client.running() => break,
+            Ok((id, title)) => {
+                if last_seen::check(&server, &poller, &id) == Seen::New
+                    && client.in_channels(&channel.channels)
+                {
+                    let message = format!(
+                        "New {} video: {} https://youtu.be/{}",
+                        channel.name, title, id
+                    );
+                    let sent = channel
+                        .channels
+                        .iter()
+                        .map(|irc_channel| client.send_to_channel(irc_channel, &message))
+                        .collect::<Vec<_>>();
+                    if future::join_all(sent.into_iter().map(|sent| sent.wait()))
+                        .await
+                        .into_iter()
+                        .all(|sent| sent)
+                    {
+                        last_seen::mark(&server, &poller, &id);
+                    }
+                }
+                interval
+            }
+            Err(e) => {
+                e.report(&format!("Cannot poll YouTube channel {:?}", channel.name));
+                e.retry_delay(interval)
+            }
+        };
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Turn an ISO 8601 duration like `PT1H2M3S` into `1:02:03`. Live streams have a duration of
+/// `P0D`.
+fn format_duration(duration: &str) -> String {
+    let mut seconds = 0;
+    let mut number = 0;
+    for c in duration.chars() {
+        match c {
+            '0'..='9' => number = number * 10 + c.to_digit(10).unwrap_or_default() as u64,
+            'D' => seconds += number * 24 * 60 * 60,
+            'H' => seconds += number * 60 * 60,
+            'M' => seconds += number * 60,
+            'S' => seconds += number,
+            _ => {}
+        }
+        if !c.is_ascii_digit() {
+            number = 0;
+        }
+    }
+    match seconds {
+        0 => String::from("live"),
+        s if s >= 60 * 60 => format!("{}:{:02}:{:02}", s / 3600, s / 60 % 60, s % 60),
+        s => format!("{}:{:02}", s / 60, s % 60),
+    }
+}
+
+#[test]
+fn test_video_ids() {
+    assert_eq!(
+        video_ids(
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ and youtu.be/J---aiyznGQ?t=1 or \
+             https://youtube.com/watch?feature=share&v=dQw4w9WgXcQ https://youtube.com/shorts/abcdefghijk"
+        ),
+        vec!["dQw4w9WgXcQ", "J---aiyznGQ", "abcdefghijk"]
+    );
+    assert!(video_ids("https://youtube.com/channel/UCabc").is_empty());
+}
+
+#[test]
+fn test_format_duration() {
+    assert_eq!(format_duration("PT4M5S"), "4:05");
+    assert_eq!(format_duration("PT1H2M3S"), "1:02:03");
+    assert_eq!(format_duration("PT30S"), "0:30");
+    assert_eq!(format_duration("P1DT1S"), "24:00:01");
+    assert_eq!(format_duration("P0D"), "live");
+}
@@ -0,0 +1,205 @@
+//! Authentication
+//!
+//! Logs the bot in to its account. If `sasl` is configured this happens during capability
+//! negotiation, so the bot is logged in before it joins any channel. Otherwise, or if SASL fails,
+//! the bot identifies with NickServ once the MOTD has been received.
+
+use crate::{error::Error, ConfigServer};
+use irc::client::{prelude::*, Sender};
+use irc::proto::CapSubCommand;
+use tracing::{info, warn};
+
+/// The maximum length of a single AUTHENTICATE message
+const MAX_AUTHENTICATE_BYTES: usize = 400;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SaslMechanism {
+    /// Log in with the account name and `password`
+    Plain,
+    /// Log in with the TLS client certificate in `connection.client_cert_path`
+    External,
+}
+
+impl SaslMechanism {
+    fn name(self) -> &'static str {
+        match self {
+            SaslMechanism::Plain => "PLAIN",
+            SaslMechanism::External => "EXTERNAL",
+        }
+    }
+}
+
+pub struct Authentication {
+    nickname: String,
+    account: String,
+    password: Option<String>,
+    mechanism: Option<SaslMechanism>,
+    /// Whether capability negotiation is still waiting for SASL to finish
+    negotiating: bool,
+    logged_in: bool,
+}
+
+impl Authentication {
+    pub fn new(server_config: &ConfigServer) -> Self {
+        Self {
+            nickname: server_config.nickname.clone(),
+            account: server_config.account_name().to_owned(),
+            password: server_config.password.clone().filter(|p| !p.is_empty()),
+            mechanism: server_config.sasl,
+            negotiating: server_config.sasl.is_some(),
+            logged_in: false,
+        }
+    }
+
+    /// Register with the server
+    pub fn start(&self, client: &Client) -> Result<(), Error> {
+        // extended-join lets us see the NickServ account of users joining a channel. Capabilities
+        // are requested one by one, because the server rejects a request as a whole if it does
+        // not support one of them.
+        client.send_cap_req(&[Capability::ExtendedJoin])?;
+        if self.mechanism.is_none() {
+            return Ok(client.identify()?);
+        }
+
+        // `identify` ends capability negotiation straight away, so register by hand and end it
+        // once SASL is done
+        client.send_cap_req(&[Capability::Sasl])?;
+        client.send(Command::NICK(self.nickname.clone()))?;
+        client.send(Command::USER(
+            self.nickname.clone(),
+            String::from("0"),
+            self.nickname.clone(),
+        ))?;
+        Ok(())
+    }
+
+    /// Continue logging in, based on a message from the server
+    pub fn handle(&mut self, sender: &Sender, command: &Command) -> Result<(), Error> {
+        match command {
+            Command::CAP(_, subcommand, first, second) => {
+                let supports_sasl = first
+                    .iter()
+                    .chain(second)
+                    .flat_map(|caps| caps.split_whitespace())
+                    .any(|cap| cap == Capability::Sasl.as_ref());
+                match (self.mechanism, subcommand) {
+                    (Some(mechanism), CapSubCommand::ACK) if supports_sasl => {
+                        if mechanism == SaslMechanism::Plain && self.password.is_none() {
+                            warn!("SASL PLAIN needs a password");
+                            return self.end_negotiation(sender);
+                        }
+                        sender.send_sasl(mechanism.name())
+                    }
+                    (Some(_), CapSubCommand::NAK) if supports_sasl => {
+                        warn!("The server does not support SASL");
+                        return self.end_negotiation(sender);
+                    }
+                    _ => Ok(()),
+                }
+            }
+            Command::AUTHENTICATE(data) if data == "+" => {
+                let payload = match (self.mechanism, &self.password) {
+                    (Some(SaslMechanism::Plain), Some(password)) => {
+                        format!("{}\0{}\0{}", self.account, self.account, password)
+                    }
+                    _ => String::new(),
+                };
+                authenticate_messages(&payload)
+                    .into_iter()
+                    .try_for_each(|message| sender.send_sasl(message))
+            }
+            Command::Response(Response::RPL_LOGGEDIN, _) => {
+                info!(account = %self.account, "Logged in");
+                self.logged_in = true;
+                Ok(())
+            }
+            Command::Response(Response::RPL_SASLSUCCESS, _) => return self.end_negotiation(sender),
+            Command::Response(Response::ERR_SASLALREADY, _) => {
+                self.logged_in = true;
+                return self.end_negotiation(sender);
+            }
+            // Without this, a failure would leave the server waiting for CAP END forever. NickServ
+            // is tried once the MOTD arrives.
+            Command::Response(response, args) if self.negotiating && is_sasl_failure(*response) => {
+                warn!(?response, ?args, "SASL authentication failed");
+                return self.end_negotiation(sender);
+            }
+            Command::Response(Response::RPL_ENDOFMOTD | Response::ERR_NOMOTD, _) => {
+                match &self.password {
+                    Some(password) if !self.logged_in => sender.send_privmsg(
+                        "NickServ",
+                        format!("identify {} {}", self.account, password),
+                    ),
+                    _ => Ok(()),
+                }
+            }
+            _ => Ok(()),
+        }
+        .map_err(Error::from)
+    }
+
+    fn end_negotiation(&mut self, sender: &Sender) -> Result<(), Error> {
+        self.negotiating = false;
+        Ok(sender.send(Command::CAP(None, CapSubCommand::END, None, None))?)
+    }
+}
+
+/// Whether `response` means SASL failed. These are all the failure numerics of the IRCv3 SASL
+/// spec, registration errors like ERR_NICKNAMEINUSE are left to the irc crate.
+fn is_sasl_failure(response: Response) -> bool {
+    matches!(
+        response,
+        Response::ERR_NICKLOCKED
+            | Response::ERR_SASLFAIL
+            | Response::ERR_SASLTOOLONG
+            | Response::ERR_SASLABORT
+    )
+}
+
+/// Encode `payload` as base64 and split it over AUTHENTICATE messages. A message of exactly the
+/// maximum length is followed by `+`, so the server knows the payload has ended.
+fn authenticate_messages(payload: &str) -> Vec<String> {
+    let encoded = base64::encode(payload);
+    let mut messages = encoded
+        .as_bytes()
+        .chunks(MAX_AUTHENTICATE_BYTES)
+        .map(|chunk| String::from_utf8_lossy(chunk).into_owned())
+        .collect::<Vec<_>>();
+    if messages
+        .last()
+        .is_none_or(|last| last.len() == MAX_AUTHENTICATE_BYTES)
+    {
+        messages.push(String::from("+"));
+    }
+    messages
+}
+
+#[test]
+fn test_authenticate_messages() {
+    assert_eq!(authenticate_messages(""), vec!["+"]);
+    assert_eq!(
+        authenticate_messages("TrangarBot\0TrangarBot\0hunter2"),
+        vec!["VHJhbmdhckJvdABUcmFuZ2FyQm90AGh1bnRlcjI="]
+    );
+
+    // 300 bytes encode to exactly 400 bytes of base64
+    let messages = authenticate_messages(&"a".repeat(300));
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0].len(), 400);
+    assert_eq!(messages[1], "+");
+
+    let messages = authenticate_messages(&"a".repeat(301));
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[1].len(), 4);
+}
+
+#[test]
+fn test_is_sasl_failure() {
+    assert!(is_sasl_failure(Response::ERR_NICKLOCKED));
+    assert!(is_sasl_failure(Response::ERR_SASLFAIL));
+    assert!(is_sasl_failure(Response::ERR_SASLABORT));
+    assert!(!is_sasl_failure(Response::RPL_SASLSUCCESS));
+    assert!(!is_sasl_failure(Response::RPL_SASLMECHS));
+    assert!(!is_sasl_failure(Response::ERR_NICKNAMEINUSE));
+}
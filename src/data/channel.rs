@@ -1,6 +1,9 @@
+use super::topic::TopicManager;
+use crate::error::Error;
 use irc::client::data::User as IrcUser;
 use parking_lot::RwLock;
 use std::sync::Arc;
+use tracing::warn;
 
 #[derive(Clone)]
 pub struct Channel(Arc<RwLock<ChannelInner>>);
@@ -8,14 +11,16 @@ pub struct Channel(Arc<RwLock<ChannelInner>>);
 struct ChannelInner {
     pub name: String,
     pub topic: String,
+    pub topic_manager: TopicManager,
     pub users: Vec<User>,
 }
 
 impl Channel {
-    pub fn new(name: String) -> Self {
+    pub fn new(name: String, topic_segments: Vec<String>) -> Self {
         Self(Arc::new(RwLock::new(ChannelInner {
             name,
             topic: String::new(),
+            topic_manager: TopicManager::new(topic_segments),
             users: Vec::new(),
         })))
     }
@@ -26,6 +31,9 @@ impl Channel {
     pub fn topic(&self) -> String {
         self.0.read().topic.clone()
     }
+    pub fn topic_segments(&self) -> Vec<String> {
+        self.0.read().topic_manager.segments().to_vec()
+    }
 
     pub fn add_user(&self, mut name: String) {
         let mut flags = Vec::new();
@@ -52,7 +60,7 @@ impl Channel {
         let user = match users.iter_mut().find(|u| u.name == name) {
             Some(u) => u,
             None => {
-                eprintln!("Warning: Adding op to a user that doesn't exist");
+                warn!(user = name, "Adding op to a user that doesn't exist");
                 users.push(User {
                     name: name.to_owned(),
                     flags: Vec::new(),
@@ -69,7 +77,7 @@ impl Channel {
         let user = match users.iter_mut().find(|u| u.name == name) {
             Some(u) => u,
             None => {
-                eprintln!("Warning: Removing op from a user that doesn't exist");
+                warn!(user = name, "Removing op from a user that doesn't exist");
                 users.push(User {
                     name: name.to_owned(),
                     flags: Vec::new(),
@@ -100,17 +108,29 @@ impl Channel {
         let mut inner = self.0.write();
         inner.topic = new_topic;
     }
+    pub fn queue_topic_segment(&self, segment: &str, text: String) -> Result<(), Error> {
+        self.0.write().topic_manager.queue(segment, text)
+    }
+    /// Apply the queued topic segments to the current topic. Returns the new topic if it changed.
+    pub fn apply_topic_segments(&self) -> Option<String> {
+        let mut inner = self.0.write();
+        let inner = &mut *inner;
+        inner.topic_manager.apply(&inner.topic)
+    }
     pub fn user_is_op(&self, user: &str) -> bool {
         let inner = self.0.read();
         if let Some(user) = inner.users.iter().find(|u| u.name == user) {
-            if user.flags.iter().any(|f| *f == '@') {
+            if user.flags.contains(&'@') {
                 return true;
             }
             false
         } else {
-            eprintln!("Tried to look up a user but it could not be found");
-            eprintln!("Channel {:?} - user {:?}", inner.name, user);
-            eprintln!("All users: {:?}", inner.users);
+            warn!(
+                channel = %inner.name,
+                user,
+                users = ?inner.users,
+                "Tried to look up a user but it could not be found"
+            );
             false
         }
     }
@@ -1,30 +1,58 @@
-use irc::client::Sender;
+use irc::client::{
+    prelude::{ChannelMode, Mode},
+    Sender,
+};
 use parking_lot::RwLock;
-use std::sync::Arc;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::warn;
 
 mod channel;
+mod outgoing;
+mod topic;
 
-pub use self::channel::{Channel, User};
-use crate::{Config, ConfigServer};
+pub use self::channel::Channel;
+use self::outgoing::{Outgoing, Priority, Sent};
+use crate::{error::Error, Config, ConfigServer};
+
+/// How long pollers wait for the bot to join their channels before they start
+const JOIN_TIMEOUT: Duration = Duration::from_secs(60);
 
 #[derive(Clone)]
 pub struct Client(Arc<RwLock<ClientInner>>);
 
 struct ClientInner {
     pub running: bool,
-    pub sender: Sender,
+    outgoing: Outgoing,
     pub config: Arc<Config>,
+    /// The host of the server in config.json. This is not necessarily the host we're connected
+    /// to, see `connection`.
     server_host: String,
+    connection: Connection,
     pub channels: Vec<Channel>,
 }
 
 impl Client {
-    pub fn new(config: Arc<Config>, server_host: String, sender: Sender) -> Self {
+    pub fn new(
+        config: Arc<Config>,
+        server_host: String,
+        connection: Connection,
+        sender: Sender,
+    ) -> Self {
+        let nickname = config
+            .servers
+            .iter()
+            .find(|s| s.host == server_host)
+            .map(|s| s.nickname.clone())
+            .unwrap_or_default();
         Self(Arc::new(RwLock::new(ClientInner {
             running: true,
-            sender,
+            outgoing: Outgoing::spawn(nickname, sender),
             config,
             server_host,
+            connection,
             channels: Vec::new(),
         })))
     }
@@ -33,6 +61,19 @@ impl Client {
         self.0.read().server_config()
     }
 
+    pub fn connection(&self) -> Connection {
+        self.0.read().connection.clone()
+    }
+
+    /// Remember when the server accepted the connection
+    pub fn set_connected_at(&self, connected_at: Instant) {
+        self.0.write().connection.connected_at = Some(connected_at);
+    }
+
+    pub fn config(&self) -> Arc<Config> {
+        Arc::clone(&self.0.read().config)
+    }
+
     pub fn running(&self) -> bool {
         self.0.read().running
     }
@@ -47,7 +88,13 @@ impl Client {
             channel
         } else {
             let mut inner = self.0.write();
-            let channel = Channel::new(name);
+            let topic_segments = inner
+                .server_config()
+                .topic_segments
+                .get(&name)
+                .cloned()
+                .unwrap_or_default();
+            let channel = Channel::new(name, topic_segments);
             inner.channels.push(channel.clone());
             channel
         }
@@ -64,25 +111,101 @@ impl Client {
         None
     }
 
-    pub fn send_to_channel(&self, channel: &str, message: impl std::fmt::Display) {
-        let inner = self.0.read();
-        if let Err(e) = inner.sender.send_privmsg(channel, &message) {
-            eprintln!("Could not send message to {}", channel);
-            eprintln!("Message: {}", message);
-            eprintln!("Error: {:?}", e);
-            // TODO: shut down client by calling `self.set_running(false);` ?
+    /// Whether the bot has joined all of `channels`. Messages to a channel the bot is not in are
+    /// rejected by the server.
+    pub fn in_channels(&self, channels: &[String]) -> bool {
+        channels
+            .iter()
+            .all(|channel| self.find_channel(channel).is_some())
+    }
+
+    /// Wait until the bot has joined all of `channels`, which happens after registering with the
+    /// server. Gives up after `JOIN_TIMEOUT`, e.g. because a channel is missing from `channels` in
+    /// config.json.
+    pub async fn wait_for_channels(&self, channels: &[String]) {
+        let deadline = Instant::now() + JOIN_TIMEOUT;
+        while !self.in_channels(channels) {
+            if !self.running() {
+                return;
+            }
+            if Instant::now() >= deadline {
+                warn!(
+                    ?channels,
+                    "Not in every channel, holding back announcements"
+                );
+                return;
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
         }
     }
 
+    /// Send a message that the bot sends by itself, e.g. an announcement. These are sent after
+    /// any pending replies.
+    pub fn send_to_channel(&self, channel: &str, message: impl std::fmt::Display) -> Sent {
+        let inner = self.0.read();
+        inner
+            .outgoing
+            .privmsg(channel, &message.to_string(), Priority::Announcement)
+    }
+
+    /// Send a response to something a user said
+    pub fn send_reply(&self, target: &str, message: impl std::fmt::Display) {
+        let inner = self.0.read();
+        inner
+            .outgoing
+            .privmsg(target, &message.to_string(), Priority::Reply);
+    }
+
+    pub fn send_notice(&self, target: &str, message: impl std::fmt::Display) {
+        let inner = self.0.read();
+        inner
+            .outgoing
+            .notice(target, &message.to_string(), Priority::Reply);
+    }
+
     pub fn set_channel_topic(&self, channel: &str, topic: impl std::fmt::Display) {
         let inner = self.0.read();
-        if let Err(e) = inner.sender.send_topic(channel, &topic) {
-            eprintln!("Could not set channel {:?}'s topic", channel);
-            eprintln!("Message: {}", topic);
-            eprintln!("Error: {:?}", e);
+        inner
+            .outgoing
+            .topic(channel, &topic.to_string(), Priority::Announcement);
+    }
+
+    /// Replace a named segment of the channel topic. The topic is requested from the server first,
+    /// and the segment is replaced once it arrives in [`Client::on_topic_received`].
+    pub fn set_topic_segment(
+        &self,
+        channel: &str,
+        segment: &str,
+        text: String,
+    ) -> Result<(), Error> {
+        self.find_channel(channel)
+            .ok_or_else(|| Error::Irc(format!("Not in channel {}", channel)))?
+            .queue_topic_segment(segment, text)?;
+        self.0
+            .read()
+            .outgoing
+            .topic(channel, "", Priority::Announcement);
+        Ok(())
+    }
+
+    /// Called when the server tells us what the topic of a channel is
+    pub fn on_topic_received(&self, channel: &str, topic: String) {
+        let channel_data = self.find_or_create_channel(channel.to_owned());
+        channel_data.set_topic(topic);
+        if let Some(new_topic) = channel_data.apply_topic_segments() {
+            self.set_channel_topic(channel, new_topic);
         }
     }
 
+    pub fn set_user_mode(&self, channel: &str, nickname: &str, mode: ChannelMode) {
+        let inner = self.0.read();
+        inner.outgoing.mode(
+            channel,
+            vec![Mode::Plus(mode, Some(nickname.to_owned()))],
+            Priority::Reply,
+        );
+    }
+
     pub fn for_each_channel(&self, mut cb: impl FnMut(&Channel)) {
         let inner = self.0.read();
         for channel in &inner.channels {
@@ -102,6 +225,17 @@ impl ClientInner {
     }
 }
 
+#[derive(Clone, Debug)]
+pub struct Connection {
+    /// The host we're connected to, which is either the host of the server or one of its
+    /// fallback hosts
+    pub host: String,
+    /// When the server welcomed the bot, `None` until then
+    pub connected_at: Option<Instant>,
+    /// How often the bot reconnected to this server since it started
+    pub reconnects: u32,
+}
+
 pub struct Message<'a> {
     pub client: &'a Client,
     pub server_config: &'a ConfigServer,
@@ -116,6 +250,23 @@ pub struct Message<'a> {
 
 impl Message<'_> {
     pub fn reply(&self, text: impl std::fmt::Display) {
-        self.client.send_to_channel(self.reply_to, text);
+        self.client.send_reply(self.reply_to, text);
+    }
+
+    /// Reply to the sender with a NOTICE, so other users in the channel don't see it
+    pub fn reply_privately(&self, text: impl std::fmt::Display) {
+        self.client.send_notice(self.sender, text);
     }
 }
+
+pub struct Join<'a> {
+    pub client: &'a Client,
+    pub channel: &'a str,
+
+    pub nickname: &'a str,
+    pub username: &'a str,
+    pub hostname: &'a str,
+    /// The NickServ account of the user, if the server told us about it through the
+    /// `extended-join` capability
+    pub account: Option<&'a str>,
+}
@@ -0,0 +1,302 @@
+//! Outgoing messages
+//!
+//! Every PRIVMSG, NOTICE, TOPIC and MODE the bot sends goes through a queue, so that:
+//! - long messages are split over multiple lines instead of being cut off by the server
+//! - the bot does not send messages faster than the server allows, and doesn't get kicked for
+//!   flooding
+//! - replies to users are sent before announcements
+
+use irc::{
+    client::Sender,
+    proto::{ChannelMode, Command, Mode},
+};
+use std::time::{Duration, Instant};
+use tokio::sync::{
+    mpsc::{self, UnboundedReceiver, UnboundedSender},
+    oneshot,
+};
+use tracing::{error, Instrument};
+
+/// The maximum length of a line in the IRC protocol, including the trailing `\r\n`
+const MAX_LINE_BYTES: usize = 512;
+/// The longest `username@hostname` we expect the server to put in front of our messages
+const MAX_USER_HOST_BYTES: usize = 10 + 1 + 63;
+
+/// How many messages can be sent at once before the rate limit kicks in
+const BURST_SIZE: u32 = 4;
+/// How often a new message can be sent once the burst is used up
+const MESSAGE_INTERVAL: Duration = Duration::from_millis(1500);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Priority {
+    /// A direct response to something a user said
+    Reply,
+    /// Anything the bot sends by itself, e.g. new version announcements
+    Announcement,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Kind {
+    Privmsg,
+    Notice,
+}
+
+struct Line {
+    command: Command,
+    /// Notified once this line is handed to the server connection
+    sent: Option<oneshot::Sender<()>>,
+}
+
+/// Tells when a message has been sent
+pub struct Sent(oneshot::Receiver<()>);
+
+impl Sent {
+    /// Wait until the whole message is sent. Returns `false` if it never will be, e.g. because the
+    /// client disconnected first.
+    pub async fn wait(self) -> bool {
+        self.0.await.is_ok()
+    }
+}
+
+pub struct Outgoing {
+    nickname: String,
+    replies: UnboundedSender<Line>,
+    announcements: UnboundedSender<Line>,
+}
+
+impl Outgoing {
+    /// Start the task that sends queued messages to `sender`. The task stops when this `Outgoing`
+    /// is dropped.
+    pub fn spawn(nickname: String, sender: Sender) -> Self {
+        let (replies, reply_receiver) = mpsc::unbounded_channel();
+        let (announcements, announcement_receiver) = mpsc::unbounded_channel();
+        tokio::spawn(run(sender, reply_receiver, announcement_receiver).in_current_span());
+        Self {
+            nickname,
+            replies,
+            announcements,
+        }
+    }
+
+    pub fn privmsg(&self, target: &str, text: &str, priority: Priority) -> Sent {
+        self.enqueue(Kind::Privmsg, target, text, priority)
+    }
+
+    pub fn notice(&self, target: &str, text: &str, priority: Priority) -> Sent {
+        self.enqueue(Kind::Notice, target, text, priority)
+    }
+
+    /// Set the topic of a channel, or ask the server for it if `topic` is empty
+    pub fn topic(&self, channel: &str, topic: &str, priority: Priority) -> Sent {
+        let topic = Some(topic.to_owned()).filter(|topic| !topic.is_empty());
+        self.queue(vec![Command::TOPIC(channel.to_owned(), topic)], priority)
+    }
+
+    pub fn mode(&self, channel: &str, modes: Vec<Mode<ChannelMode>>, priority: Priority) -> Sent {
+        self.queue(
+            vec![Command::ChannelMODE(channel.to_owned(), modes)],
+            priority,
+        )
+    }
+
+    fn enqueue(&self, kind: Kind, target: &str, text: &str, priority: Priority) -> Sent {
+        let max_bytes = max_text_bytes(&self.nickname, kind, target);
+        let commands = split_message(text, max_bytes)
+            .into_iter()
+            .map(|text| match kind {
+                Kind::Privmsg => Command::PRIVMSG(target.to_owned(), text),
+                Kind::Notice => Command::NOTICE(target.to_owned(), text),
+            })
+            .collect();
+        self.queue(commands, priority)
+    }
+
+    fn queue(&self, commands: Vec<Command>, priority: Priority) -> Sent {
+        let queue = match priority {
+            Priority::Reply => &self.replies,
+            Priority::Announcement => &self.announcements,
+        };
+        let (sent, receiver) = oneshot::channel();
+        let mut sent = Some(sent);
+        let mut commands = commands.into_iter().peekable();
+        while let Some(command) = commands.next() {
+            let line = Line {
+                command,
+                // The message is sent once its last line is
+                sent: if commands.peek().is_none() {
+                    sent.take()
+                } else {
+                    None
+                },
+            };
+            if let Err(e) = queue.send(line) {
+                error!(command = ?e.0.command, "Could not queue message, the send queue is closed");
+            }
+        }
+        Sent(receiver)
+    }
+}
+
+async fn run(
+    sender: Sender,
+    mut replies: UnboundedReceiver<Line>,
+    mut announcements: UnboundedReceiver<Line>,
+) {
+    let mut bucket = TokenBucket::new(BURST_SIZE, MESSAGE_INTERVAL, Instant::now());
+    loop {
+        // Wait for the rate limit before picking a line, so that a reply that comes in while we
+        // wait still gets sent before any announcement
+        if let Some(wait) = bucket.time_until_available(Instant::now()) {
+            tokio::time::sleep(wait).await;
+        }
+        let line = tokio::select! {
+            biased;
+            Some(line) = replies.recv() => line,
+            Some(line) = announcements.recv() => line,
+            else => break,
+        };
+        bucket.take(Instant::now());
+
+        match sender.send(line.command.clone()) {
+            Ok(()) => {
+                if let Some(sent) = line.sent {
+                    let _ = sent.send(());
+                }
+            }
+            Err(e) => error!(command = ?line.command, error = ?e, "Could not send message"),
+        }
+    }
+}
+
+/// How many bytes of text fit in a single line. The server prepends `:nick!user@host` to our
+/// message before sending it to other users, so that has to fit in the line as well.
+fn max_text_bytes(nickname: &str, kind: Kind, target: &str) -> usize {
+    let command = match kind {
+        Kind::Privmsg => "PRIVMSG",
+        Kind::Notice => "NOTICE",
+    };
+    // ":nick!user@host COMMAND target :text\r\n"
+    let overhead =
+        1 + nickname.len() + 1 + MAX_USER_HOST_BYTES + 1 + command.len() + 1 + target.len() + 2 + 2;
+    MAX_LINE_BYTES.saturating_sub(overhead).max(1)
+}
+
+/// Split `text` in lines of at most `max_bytes` bytes. Newlines always start a new line, other
+/// lines are split on the last whitespace that fits. Words that are longer than a line are split
+/// on a character boundary.
+fn split_message(text: &str, max_bytes: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for mut remaining in text.lines().map(str::trim_end) {
+        while remaining.len() > max_bytes {
+            let mut end = max_bytes;
+            while !remaining.is_char_boundary(end) {
+                end -= 1;
+            }
+            if end == 0 {
+                // A single character that is longer than a line, send it anyway
+                end = remaining.chars().next().map(char::len_utf8).unwrap_or(1);
+            }
+            let split = if remaining[end..].starts_with(char::is_whitespace) {
+                end
+            } else {
+                remaining[..end]
+                    .rfind(char::is_whitespace)
+                    .filter(|index| *index > 0)
+                    .unwrap_or(end)
+            };
+            lines.push(remaining[..split].to_owned());
+            remaining = remaining[split..].trim_start();
+        }
+        if !remaining.is_empty() {
+            lines.push(remaining.to_owned());
+        }
+    }
+    lines
+}
+
+struct TokenBucket {
+    capacity: u32,
+    tokens: u32,
+    interval: Duration,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(capacity: u32, interval: Duration, now: Instant) -> Self {
+        Self {
+            capacity,
+            tokens: capacity,
+            interval,
+            last_refill: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        while self.tokens < self.capacity && now >= self.last_refill + self.interval {
+            self.tokens += 1;
+            self.last_refill += self.interval;
+        }
+        if self.tokens == self.capacity {
+            self.last_refill = now;
+        }
+    }
+
+    /// How long to wait until a token is available, or `None` if one is available right now
+    fn time_until_available(&mut self, now: Instant) -> Option<Duration> {
+        self.refill(now);
+        if self.tokens > 0 {
+            None
+        } else {
+            Some((self.last_refill + self.interval).saturating_duration_since(now))
+        }
+    }
+
+    fn take(&mut self, now: Instant) {
+        self.refill(now);
+        self.tokens = self.tokens.saturating_sub(1);
+    }
+}
+
+#[test]
+fn test_split_message() {
+    assert_eq!(split_message("", 10), Vec::<String>::new());
+    assert_eq!(split_message("short", 10), vec!["short"]);
+    assert_eq!(
+        split_message("one two three four", 10),
+        vec!["one two", "three four"]
+    );
+    assert_eq!(
+        split_message("line one\nline two", 100),
+        vec!["line one", "line two"]
+    );
+    assert_eq!(
+        split_message("averyveryverylongword", 10),
+        vec!["averyveryv", "erylongwor", "d"]
+    );
+    // Never split inside of a multi-byte character
+    let lines = split_message("ééééé ééééé", 5);
+    assert!(lines.iter().all(|line| line.len() <= 5));
+    assert_eq!(lines.concat(), "éééééééééé");
+}
+
+#[test]
+fn test_token_bucket() {
+    let start = Instant::now();
+    let interval = Duration::from_secs(1);
+    let mut bucket = TokenBucket::new(2, interval, start);
+
+    assert_eq!(bucket.time_until_available(start), None);
+    bucket.take(start);
+    assert_eq!(bucket.time_until_available(start), None);
+    bucket.take(start);
+    assert_eq!(bucket.time_until_available(start), Some(interval));
+
+    let later = start + Duration::from_millis(400);
+    assert_eq!(
+        bucket.time_until_available(later),
+        Some(Duration::from_millis(600))
+    );
+    assert_eq!(bucket.time_until_available(start + interval), None);
+    assert_eq!(bucket.time_until_available(start + interval * 10), None);
+    assert_eq!(bucket.tokens, 2);
+}
@@ -0,0 +1,101 @@
+//! Channel topics
+//!
+//! A topic consists of segments separated by `|`. Which segment is which is configured per
+//! channel in `topic_segments`, so that actions can change e.g. the `version` segment without
+//! knowing where in the topic it is.
+//!
+//! Changes are not written straight away. The bot asks the server for the current topic first, and
+//! applies the changes to the topic the server replies with. This way edits that someone made to
+//! the topic after we last saw it are not overwritten.
+
+use crate::error::Error;
+
+pub struct TopicManager {
+    /// The names of the segments, in the order they appear in the topic
+    segments: Vec<String>,
+    /// Changes that are waiting for the server to send us the current topic
+    pending: Vec<(usize, String)>,
+}
+
+impl TopicManager {
+    pub fn new(segments: Vec<String>) -> Self {
+        Self {
+            segments,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn segments(&self) -> &[String] {
+        &self.segments
+    }
+
+    /// Queue `text` to be put in `segment`. The change is applied the next time the topic is
+    /// received from the server.
+    pub fn queue(&mut self, segment: &str, text: String) -> Result<(), Error> {
+        let index = self
+            .segments
+            .iter()
+            .position(|s| s.eq_ignore_ascii_case(segment))
+            .ok_or_else(|| Error::Config(format!("Unknown topic segment {:?}", segment)))?;
+        self.pending.retain(|(i, _)| *i != index);
+        self.pending.push((index, text));
+        Ok(())
+    }
+
+    /// Apply the queued changes to `topic`, which was just received from the server. Returns the
+    /// new topic if anything changed.
+    pub fn apply(&mut self, topic: &str) -> Option<String> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        let new_topic = replace_segments(topic, &std::mem::take(&mut self.pending));
+        if new_topic == topic {
+            None
+        } else {
+            Some(new_topic)
+        }
+    }
+}
+
+/// Replace the segments at the given indices. If the topic has fewer segments, empty ones are
+/// added.
+fn replace_segments(topic: &str, changes: &[(usize, String)]) -> String {
+    let mut segments: Vec<String> = if topic.trim().is_empty() {
+        Vec::new()
+    } else {
+        topic.split('|').map(|s| s.trim().to_owned()).collect()
+    };
+    for (index, text) in changes {
+        if segments.len() <= *index {
+            segments.resize(index + 1, String::new());
+        }
+        segments[*index] = text.trim().to_owned();
+    }
+    segments.join(" | ")
+}
+
+#[test]
+fn test_topic_manager() {
+    let mut manager = TopicManager::new(vec![
+        String::from("welcome"),
+        String::from("version"),
+        String::from("fff"),
+    ]);
+    assert!(manager.queue("unknown", String::from("text")).is_err());
+    assert_eq!(manager.apply("Welcome | 1.0 | FFF #1"), None);
+
+    manager.queue("fff", String::from("FFF #2")).unwrap();
+    manager.queue("Version", String::from("1.0")).unwrap();
+    manager.queue("version", String::from("1.1")).unwrap();
+    // Someone changed the welcome segment since we last saw the topic, that edit is kept
+    assert_eq!(
+        manager.apply("Hello|1.0|FFF #1|Extra"),
+        Some(String::from("Hello | 1.1 | FFF #2 | Extra"))
+    );
+    assert_eq!(manager.apply("Hello | 1.1 | FFF #2 | Extra"), None);
+
+    manager.queue("fff", String::from("FFF #3")).unwrap();
+    assert_eq!(manager.apply(""), Some(String::from(" |  | FFF #3")));
+    manager.queue("welcome", String::from("Hello")).unwrap();
+    assert_eq!(manager.apply("Hello"), None);
+}
@@ -0,0 +1,156 @@
+//! Errors
+//!
+//! Every fallible function in the bot returns an [`Error`], so that callers can tell what kind of
+//! thing went wrong. Network errors and HTTP errors usually go away by themselves, while parse
+//! errors mostly mean that a website changed its layout and the bot has to be updated.
+
+use reqwest::StatusCode;
+use std::{fmt, time::Duration};
+use tracing::{error, warn};
+
+/// How many poll intervals a poller waits after a server told it that it sends too many requests,
+/// if the server did not say how long to wait
+const RATE_LIMITED_INTERVALS: u32 = 4;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    /// A request could not be sent, or no response came back
+    Network(String),
+    /// A server responded with an error status
+    Http {
+        status: StatusCode,
+        /// How long the server asked us to wait before trying again
+        retry_after: Option<Duration>,
+    },
+    /// A response could not be understood
+    Parse(String),
+    /// The thing a user asked for does not exist
+    NotFound(String),
+    /// Something went wrong talking to the IRC server
+    Irc(String),
+    /// config.json is missing a setting or has an invalid one
+    Config(String),
+    /// Something in the `persist/` folder could not be read or written
+    Persist(String),
+}
+
+impl Error {
+    /// Whether someone has to fix the bot or its configuration for this error to go away
+    pub fn needs_attention(&self) -> bool {
+        matches!(self, Error::Parse(_) | Error::Config(_) | Error::Persist(_))
+    }
+
+    /// How long a task that polls every `interval` should wait before trying again after this
+    /// error. Servers that say we send too many requests get at least a few intervals of rest.
+    pub fn retry_delay(&self, interval: Duration) -> Duration {
+        match self {
+            Error::Http {
+                status: StatusCode::TOO_MANY_REQUESTS,
+                retry_after,
+            } => retry_after
+                .unwrap_or(interval * RATE_LIMITED_INTERVALS)
+                .max(interval),
+            _ => interval,
+        }
+    }
+
+    /// Log this error. Errors that need attention are logged as errors, anything else as a
+    /// warning.
+    pub fn report(&self, context: &str) {
+        if self.needs_attention() {
+            error!(error = %self, kind = self.kind(), "{}", context);
+        } else {
+            warn!(error = %self, kind = self.kind(), "{}", context);
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Error::Network(_) => "network",
+            Error::Http { .. } => "http",
+            Error::Parse(_) => "parse",
+            Error::NotFound(_) => "not_found",
+            Error::Irc(_) => "irc",
+            Error::Config(_) => "config",
+            Error::Persist(_) => "persist",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Http { status, .. } => write!(f, "HTTP status {}", status),
+            Error::Network(message)
+            | Error::Parse(message)
+            | Error::NotFound(message)
+            | Error::Irc(message)
+            | Error::Config(message)
+            | Error::Persist(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Urls are left out of the error, because some urls contain api keys and errors can end up in a
+/// channel
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        if let Some(status) = e.status() {
+            Error::Http {
+                status,
+                retry_after: None,
+            }
+        } else if e.is_decode() {
+            Error::Parse(e.without_url().to_string())
+        } else {
+            Error::Network(e.without_url().to_string())
+        }
+    }
+}
+
+impl From<irc::error::Error> for Error {
+    fn from(e: irc::error::Error) -> Self {
+        Error::Irc(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Parse(e.to_string())
+    }
+}
+
+impl From<url::ParseError> for Error {
+    fn from(e: url::ParseError) -> Self {
+        Error::Parse(format!("Invalid url: {}", e))
+    }
+}
+
+#[test]
+fn test_retry_delay() {
+    let interval = Duration::from_secs(60);
+    let rate_limited = |retry_after| Error::Http {
+        status: StatusCode::TOO_MANY_REQUESTS,
+        retry_after,
+    };
+    assert_eq!(rate_limited(None).retry_delay(interval), interval * 4);
+    assert_eq!(
+        rate_limited(Some(Duration::from_secs(600))).retry_delay(interval),
+        Duration::from_secs(600)
+    );
+    assert_eq!(
+        rate_limited(Some(Duration::from_secs(1))).retry_delay(interval),
+        interval
+    );
+    let server_error = Error::Http {
+        status: StatusCode::INTERNAL_SERVER_ERROR,
+        retry_after: None,
+    };
+    assert_eq!(server_error.retry_delay(interval), interval);
+    assert_eq!(
+        Error::Network(String::from("timed out")).retry_delay(interval),
+        interval
+    );
+}
@@ -0,0 +1,109 @@
+//! HTTP requests
+//!
+//! Every request the bot makes goes through a single client, so they all share a connection pool,
+//! user agent and timeout.
+
+use crate::error::Error;
+use reqwest::{
+    header::{HeaderMap, RETRY_AFTER},
+    Response, StatusCode,
+};
+use serde::de::DeserializeOwned;
+use std::time::Duration;
+
+const USER_AGENT: &str = concat!("TrangarBot/", env!("CARGO_PKG_VERSION"));
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+lazy_static::lazy_static! {
+    static ref CLIENT: reqwest::Client = reqwest::Client::builder()
+        .user_agent(USER_AGENT)
+        .timeout(TIMEOUT)
+        .build()
+        .expect("Could not create the http client");
+}
+
+/// Send a GET request. Responses with an error status are returned as an error.
+pub async fn get(url: &str) -> Result<Response, Error> {
+    let response = CLIENT.get(url).send().await?;
+    check_status(response)
+}
+
+pub async fn get_text(url: &str) -> Result<String, Error> {
+    Ok(get(url).await?.text().await?)
+}
+
+pub async fn get_json<T: DeserializeOwned>(url: &str) -> Result<T, Error> {
+    Ok(get(url).await?.json().await?)
+}
+
+/// Like [`get_json`], but returns `None` if the server responds with 404 Not Found
+pub async fn get_json_if_found<T: DeserializeOwned>(url: &str) -> Result<Option<T>, Error> {
+    let response = CLIENT.get(url).send().await?;
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    Ok(Some(check_status(response)?.json().await?))
+}
+
+/// Replace the most common html entities
+pub fn unescape_html(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+/// Cut `text` off at a word boundary if it is longer than `max_length` bytes
+pub fn shorten(text: &str, max_length: usize) -> String {
+    if text.len() <= max_length {
+        return text.to_owned();
+    }
+    let mut end = max_length;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    let end = text[..end].rfind(' ').unwrap_or(end);
+    format!("{}...", &text[..end])
+}
+
+fn check_status(response: Response) -> Result<Response, Error> {
+    let status = response.status();
+    if !status.is_client_error() && !status.is_server_error() {
+        return Ok(response);
+    }
+    Err(Error::Http {
+        status,
+        retry_after: retry_after(response.headers()),
+    })
+}
+
+/// How long the Retry-After header asks us to wait. Retry-After can also be a date, but the sites
+/// we use only send seconds.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    headers
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok())
+        .map(Duration::from_secs)
+}
+
+#[test]
+fn test_retry_after() {
+    let mut headers = HeaderMap::new();
+    assert_eq!(retry_after(&headers), None);
+    headers.insert(RETRY_AFTER, "120".parse().unwrap());
+    assert_eq!(retry_after(&headers), Some(Duration::from_secs(120)));
+    headers.insert(
+        RETRY_AFTER,
+        "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap(),
+    );
+    assert_eq!(retry_after(&headers), None);
+}
+
+#[test]
+fn test_shorten() {
+    assert_eq!(shorten("short text", 20), "short text");
+    assert_eq!(shorten("a few more words", 10), "a few...");
+}
@@ -0,0 +1,63 @@
+//! Logging
+//!
+//! Everything is logged through `tracing`, to stdout and optionally as json lines to a file. The
+//! `filter` in config.json decides what is logged, e.g. `info` or `info,trangarbot::auth=debug`.
+//! The `RUST_LOG` environment variable overrides it.
+
+use crate::error::Error;
+use std::path::Path;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    pub filter: String,
+    /// A file that logs are appended to as json, one object per line
+    pub json_file: Option<String>,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            filter: String::from("info"),
+            json_file: None,
+        }
+    }
+}
+
+/// Start logging. The returned guard writes the remaining logs to the json file when it is
+/// dropped, so it should be kept alive until the bot stops.
+pub fn init(config: &LoggingConfig) -> Result<Option<WorkerGuard>, Error> {
+    let filter = || {
+        EnvFilter::try_from_default_env()
+            .or_else(|_| EnvFilter::try_new(&config.filter))
+            .map_err(|e| Error::Config(format!("Invalid log filter {:?}: {}", config.filter, e)))
+    };
+
+    let (json_layer, guard) = match &config.json_file {
+        Some(json_file) => {
+            let path = Path::new(json_file);
+            let file_name = path
+                .file_name()
+                .ok_or_else(|| Error::Config(format!("Invalid log file {:?}", json_file)))?;
+            let directory = path.parent().unwrap_or_else(|| Path::new("."));
+            let (writer, guard) = tracing_appender::non_blocking(tracing_appender::rolling::never(
+                directory, file_name,
+            ));
+            let layer = fmt::layer()
+                .json()
+                .with_writer(writer)
+                .with_filter(filter()?);
+            (Some(layer), Some(guard))
+        }
+        None => (None, None),
+    };
+
+    tracing_subscriber::registry()
+        .with(fmt::layer().with_filter(filter()?))
+        .with(json_layer)
+        .try_init()
+        .map_err(|e| Error::Config(format!("Could not start logging: {}", e)))?;
+    Ok(guard)
+}
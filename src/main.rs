@@ -2,65 +2,130 @@
 extern crate serde_derive;
 
 mod actions;
+mod auth;
 mod data;
+mod error;
+mod http;
+mod logging;
+mod persist;
+mod reconnect;
 
+use error::Error;
 use futures::StreamExt;
 use irc::client::{data::Config as IrcConfig, prelude::*};
 use serde_derive::{Deserialize, Serialize};
-use std::{convert::Infallible, sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::{debug, info, info_span, warn, Instrument};
 
 #[tokio::main]
 async fn main() {
     let config = Arc::new(Config::from_file("config.json").expect("Could not load config"));
+    let _log_guard = logging::init(&config.logging).expect("Could not start logging");
+    for server in config.servers.iter().filter(|s| s.uses_default_feeds) {
+        info!(
+            server = %server.host,
+            channel = ?server.factorio_channel,
+            "No feeds configured, announcing the default feeds"
+        );
+    }
 
     let tasks = (0..config.servers.len()).map(|server_index| {
         let config = Arc::clone(&config);
+        let span = info_span!("server", server = %config.servers[server_index].host);
         async move {
             let server = &config.servers[server_index];
-            loop {
-                if let Err(e) = run_client(Arc::clone(&config), server).await {
-                    eprintln!("Client {} disconnected: {:?}", server.host, e);
-                    tokio::time::sleep(Duration::from_secs(30)).await;
-                }
+            let hosts = server.hosts();
+            let mut backoff = reconnect::Backoff::new(server.connection.reconnect.clone());
+            for reconnects in 0.. {
+                let connection = data::Connection {
+                    host: backoff.host(&hosts).to_owned(),
+                    connected_at: None,
+                    reconnects,
+                };
+                let (e, connected_for) =
+                    run_client(Arc::clone(&config), server, connection.clone()).await;
+                let delay = backoff.next_delay(connected_for);
+                warn!(
+                    host = %connection.host,
+                    error = %e,
+                    delay_seconds = delay.as_secs(),
+                    "Disconnected, reconnecting"
+                );
+                tokio::time::sleep(delay).await;
             }
         }
+        .instrument(span)
     });
 
     futures::future::join_all(tasks).await;
 }
 
+/// Connect to a server and handle its messages until the connection is lost. Returns why it was
+/// lost, and how long the bot was registered with the server.
 async fn run_client(
     config: Arc<Config>,
     server_config: &ConfigServer,
-) -> Result<Infallible, String> {
-    println!("Connecting to {}", server_config.host);
-    let irc_client = Client::from_config(IrcConfig {
-        server: Some(server_config.host.clone()),
-        nickname: Some(server_config.nickname.clone()),
-        channels: server_config.channels.clone(),
-        port: Some(6697),
-        use_tls: Some(true),
-        ping_timeout: Some(60),
-        ping_time: Some(10),
-        ..Default::default()
-    })
-    .await
-    .map_err(|e| e.to_string())?;
-
-    irc_client.identify().map_err(|e| e.to_string())?;
+    connection: data::Connection,
+) -> (Error, Duration) {
+    let (irc_client, authentication) = match connect(server_config, &connection).await {
+        Ok(connected) => connected,
+        Err(e) => return (e, Duration::ZERO),
+    };
 
     let client = data::Client::new(
         Arc::clone(&config),
         server_config.host.clone(),
+        connection,
         irc_client.sender(),
     );
 
-    if let Err(e) = run_client_inner(config, server_config, irc_client, client.clone()).await {
-        client.set_running(false);
-        Err(e)
-    } else {
-        unreachable!()
-    }
+    let Err(e) = run_client_inner(
+        config,
+        server_config,
+        irc_client,
+        client.clone(),
+        authentication,
+    )
+    .await;
+    client.set_running(false);
+    actions::on_stop(&client);
+    let connected_for = client
+        .connection()
+        .connected_at
+        .map_or(Duration::ZERO, |connected_at| connected_at.elapsed());
+    (e, connected_for)
+}
+
+async fn connect(
+    server_config: &ConfigServer,
+    connection: &data::Connection,
+) -> Result<(Client, auth::Authentication), Error> {
+    info!(host = %connection.host, "Connecting");
+    let connection_config = &server_config.connection;
+    let port = connection_config.port();
+    let irc_client = Client::from_config(IrcConfig {
+        server: Some(connection.host.clone()),
+        nickname: Some(server_config.nickname.clone()),
+        channels: server_config.channels.clone(),
+        port: Some(port),
+        use_tls: Some(connection_config.use_tls),
+        cert_path: connection_config.cert_path.clone(),
+        client_cert_path: connection_config.client_cert_path.clone(),
+        client_cert_pass: connection_config.client_cert_pass.clone(),
+        ping_timeout: Some(connection_config.ping_timeout),
+        ping_time: Some(connection_config.ping_time),
+        ..Default::default()
+    })
+    .await?;
+
+    let authentication = auth::Authentication::new(server_config);
+    authentication.start(&irc_client)?;
+    Ok((irc_client, authentication))
 }
 
 async fn run_client_inner(
@@ -68,26 +133,27 @@ async fn run_client_inner(
     server_config: &ConfigServer,
     mut irc_client: Client,
     client: data::Client,
-) -> Result<Infallible, String> {
-    actions::on_start(client.clone()).await?;
+    mut authentication: auth::Authentication,
+) -> Result<Infallible, Error> {
+    actions::on_start(client.clone()).await;
 
+    let sender = irc_client.sender();
     let mut stream = irc_client.stream().unwrap();
     loop {
         let msg = match stream.next().await {
             Some(Ok(msg)) => msg,
             Some(Err(e)) => {
-                return Err(format!("Error receiving message: {:?}", e));
+                return Err(e.into());
             }
             None => {
-                return Err(String::from("IRC stream closed"));
+                return Err(Error::Irc(String::from("IRC stream closed")));
             }
         };
 
+        authentication.handle(&sender, &msg.command)?;
+
         match (&msg.prefix, &msg.command) {
-            (
-                Some(Prefix::Nickname(nickname, username, hostname)),
-                Command::PRIVMSG(channel_name, body),
-            ) => {
+            (Some(Prefix::Nickname(nickname, _, _)), Command::PRIVMSG(channel_name, body)) => {
                 let channel = client.find_channel(channel_name);
                 let message = data::Message {
                     config: &config,
@@ -102,20 +168,12 @@ async fn run_client_inner(
                     channel,
                     sender: nickname,
                 };
-                if let Err(e) = actions::on_message(&message).await {
-                    eprintln!("Could not execute action");
-                    eprintln!("Server: {:?}", server_config.host);
-                    eprintln!(
-                        "PRIVMSG {} {} {} {}: {}",
-                        nickname, username, hostname, channel_name, body
-                    );
-                    eprintln!("{:?}", e);
-                }
+                let span = info_span!("channel", channel = %message.reply_to);
+                actions::on_message(&message).instrument(span).await;
             }
-            (_, Command::Response(Response::RPL_ENDOFMOTD, _)) => {
-                if let Some(password) = server_config.password.as_ref() {
-                    client.send_to_channel("NickServ", format!("identify TrangarBot {}", password))
-                }
+            (_, Command::Response(Response::RPL_WELCOME, _)) => {
+                info!("Registered with the server");
+                client.set_connected_at(Instant::now());
             }
             (_, Command::TOPIC(channel, Some(topic))) => {
                 client
@@ -124,9 +182,12 @@ async fn run_client_inner(
             }
             (_, Command::Response(Response::RPL_TOPIC, args)) => {
                 if let (Some(channel), Some(new_topic)) = (args.get(1), args.get(2)) {
-                    if let Some(channel) = client.find_channel(channel) {
-                        channel.set_topic(new_topic.clone());
-                    }
+                    client.on_topic_received(channel, new_topic.clone());
+                }
+            }
+            (_, Command::Response(Response::RPL_NOTOPIC, args)) => {
+                if let Some(channel) = args.get(1) {
+                    client.on_topic_received(channel, String::new());
                 }
             }
             (Some(Prefix::Nickname(nickname, _, _)), Command::PART(channel, _)) => {
@@ -134,10 +195,23 @@ async fn run_client_inner(
                     channel.remove_user(nickname);
                 }
             }
-            (Some(Prefix::Nickname(nickname, _, _)), Command::JOIN(channel, None, None)) => {
+            (
+                Some(Prefix::Nickname(nickname, username, hostname)),
+                Command::JOIN(channel, account, _),
+            ) => {
+                let _span = info_span!("channel", channel = %channel).entered();
                 client
                     .find_or_create_channel(channel.clone())
                     .add_user(nickname.clone());
+                actions::on_join(&data::Join {
+                    client: &client,
+                    channel,
+                    nickname,
+                    username,
+                    hostname,
+                    // With extended-join, the account is "*" if the user is not logged in
+                    account: account.as_deref().filter(|a| *a != "*"),
+                });
             }
             (Some(Prefix::Nickname(nickname, _, _)), Command::NICK(new_nickname)) => {
                 client.for_each_channel(|channel| channel.rename_user(nickname, new_nickname));
@@ -165,7 +239,7 @@ async fn run_client_inner(
             }
             (_, Command::Response(Response::RPL_MOTD, _)) | (_, Command::PONG(_, _)) => {}
             (_, cmd) => {
-                println!("{:?}", cmd);
+                debug!(?cmd, "Unhandled command");
             }
         }
     }
@@ -177,13 +251,22 @@ pub struct Config {
     pub factorio_username: String,
     pub factorio_key: String,
     pub wolframalpha: String,
+    #[serde(default)]
+    pub logging: logging::LoggingConfig,
     pub servers: Vec<ConfigServer>,
 }
 
 impl Config {
-    pub fn from_file(f: &str) -> Result<Config, Box<dyn std::error::Error>> {
-        let fs = std::fs::File::open(f)?;
-        serde_json::from_reader(fs).map_err(Into::into)
+    pub fn from_file(f: &str) -> Result<Config, Error> {
+        let fs = std::fs::File::open(f)
+            .map_err(|e| Error::Config(format!("Could not open {}: {}", f, e)))?;
+        let mut config: Config = serde_json::from_reader(fs)
+            .map_err(|e| Error::Config(format!("Could not parse {}: {}", f, e)))?;
+        for server in &mut config.servers {
+            server.connection.validate(&server.host, server.sasl)?;
+            server.fill_defaults();
+        }
+        Ok(config)
     }
 }
 
@@ -193,5 +276,251 @@ pub struct ConfigServer {
     pub host: String,
     pub channels: Vec<String>,
     pub factorio_channel: Option<String>,
+    /// The password of the account of the bot
     pub password: Option<String>,
+    /// The account to log in to. Defaults to `nickname`.
+    #[serde(default)]
+    pub account: Option<String>,
+    /// Log in with SASL before joining channels, instead of identifying with NickServ afterwards
+    #[serde(default)]
+    pub sasl: Option<auth::SaslMechanism>,
+    /// Feeds that are announced when they have a new item. Without a `feeds` section, the
+    /// Factorio releases, Friday Facts and Alt-F4 feeds are announced in `factorio_channel`.
+    #[serde(default)]
+    pub feeds: Option<Vec<actions::FeedConfig>>,
+    /// Whether `feeds` was filled in with the default feeds. Logged once logging has started.
+    #[serde(skip)]
+    pub uses_default_feeds: bool,
+    /// Mods on the mod portal whose new releases are announced in `factorio_channel`
+    #[serde(default)]
+    pub watched_mods: Vec<String>,
+    /// YouTube channels whose new uploads are announced
+    #[serde(default)]
+    pub youtube_channels: Vec<actions::YoutubeChannelConfig>,
+    /// The prefix that commands start with, e.g. `!` in `!help`
+    #[serde(default = "default_command_prefix")]
+    pub command_prefix: String,
+    /// Alternative names for commands, e.g. `"wolfram": "wa"`
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+    /// Which actions are enabled on this server
+    #[serde(default)]
+    pub actions: ActionFilter,
+    /// Which actions are enabled in specific channels, on top of `actions`
+    #[serde(default)]
+    pub channel_actions: HashMap<String, ActionFilter>,
+    /// How to connect to the server
+    #[serde(default)]
+    pub connection: ConnectionConfig,
+    /// The names of the `|`-separated segments of the topic of each channel, e.g.
+    /// `"#factorio": ["welcome", "stable", "experimental", "fff"]`
+    #[serde(default)]
+    pub topic_segments: HashMap<String, Vec<String>>,
+}
+
+fn default_command_prefix() -> String {
+    String::from("!")
+}
+
+impl ConfigServer {
+    /// Fill in the settings that default to something based on other settings
+    fn fill_defaults(&mut self) {
+        if self.feeds.is_some() {
+            return;
+        }
+        let channel = match &self.factorio_channel {
+            Some(channel) => channel.clone(),
+            None => return,
+        };
+        self.feeds = Some(actions::default_feeds(&channel));
+        self.uses_default_feeds = true;
+        self.topic_segments
+            .entry(channel)
+            .or_insert_with(|| actions::DEFAULT_TOPIC_SEGMENTS.map(String::from).to_vec());
+    }
+
+    pub fn feeds(&self) -> &[actions::FeedConfig] {
+        self.feeds.as_deref().unwrap_or_default()
+    }
+
+    /// The host of the server, followed by its fallback hosts
+    pub fn hosts(&self) -> Vec<String> {
+        std::iter::once(self.host.clone())
+            .chain(self.connection.fallback_hosts.iter().cloned())
+            .collect()
+    }
+
+    pub fn account_name(&self) -> &str {
+        self.account.as_deref().unwrap_or(&self.nickname)
+    }
+
+    pub fn action_enabled(&self, action: &str, channel: Option<&str>) -> bool {
+        self.actions.allows(action)
+            && channel
+                .and_then(|channel| {
+                    self.channel_actions
+                        .iter()
+                        .find(|(name, _)| name.eq_ignore_ascii_case(channel))
+                })
+                .map(|(_, filter)| filter.allows(action))
+                .unwrap_or(true)
+    }
+}
+
+/// Connection settings of a server. The irc crate always connects from the default local address,
+/// so there is no setting for the address to bind to. It also connects to whatever address the
+/// system resolves first and checks the TLS certificate against the same host name, so there is no
+/// setting to prefer IPv4 or IPv6 either.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConnectionConfig {
+    /// Defaults to 6697 with TLS, 6667 without
+    pub port: Option<u16>,
+    pub use_tls: bool,
+    /// A DER file with an extra certificate to trust, e.g. the self-signed certificate of a test
+    /// server
+    pub cert_path: Option<String>,
+    /// A PKCS#12 file with the client certificate and its private key, for SASL EXTERNAL
+    pub client_cert_path: Option<String>,
+    /// The password of `client_cert_path`
+    pub client_cert_pass: Option<String>,
+    /// Seconds of silence after which the server is pinged
+    pub ping_time: u32,
+    /// Seconds to wait for a response to a ping before reconnecting
+    pub ping_timeout: u32,
+    /// Other hosts of the same network, which are tried when `host` can't be reached
+    pub fallback_hosts: Vec<String>,
+    pub reconnect: reconnect::ReconnectConfig,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        Self {
+            port: None,
+            use_tls: true,
+            cert_path: None,
+            client_cert_path: None,
+            client_cert_pass: None,
+            ping_time: 10,
+            ping_timeout: 60,
+            fallback_hosts: Vec::new(),
+            reconnect: reconnect::ReconnectConfig::default(),
+        }
+    }
+}
+
+impl ConnectionConfig {
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or(if self.use_tls { 6697 } else { 6667 })
+    }
+
+    /// Check for settings that can't work together. `sasl` is the mechanism of the server.
+    fn validate(&self, host: &str, sasl: Option<auth::SaslMechanism>) -> Result<(), Error> {
+        if sasl == Some(auth::SaslMechanism::External) && self.client_cert_path.is_none() {
+            return Err(Error::Config(format!(
+                "{}: sasl external needs client_cert_path",
+                host
+            )));
+        }
+        if self.client_cert_path.is_some() && !self.use_tls {
+            return Err(Error::Config(format!(
+                "{}: client_cert_path needs use_tls",
+                host
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ActionFilter {
+    /// If set, only these actions are enabled
+    #[serde(default)]
+    pub enabled: Option<Vec<String>>,
+    #[serde(default)]
+    pub disabled: Vec<String>,
+}
+
+impl ActionFilter {
+    pub fn allows(&self, action: &str) -> bool {
+        self.enabled
+            .as_ref()
+            .map(|enabled| enabled.iter().any(|a| a == action))
+            .unwrap_or(true)
+            && !self.disabled.iter().any(|a| a == action)
+    }
+}
+
+#[test]
+fn test_connection_config() {
+    let connection: ConnectionConfig = serde_json::from_str(r#"{"use_tls": false}"#).unwrap();
+    assert_eq!(connection.port(), 6667);
+    assert_eq!(connection.ping_timeout, 60);
+    assert_eq!(connection.validate("irc", None), Ok(()));
+
+    let connection = ConnectionConfig {
+        client_cert_path: Some(String::from("bot.p12")),
+        ..ConnectionConfig::default()
+    };
+    assert_eq!(connection.port(), 6697);
+    assert_eq!(connection.validate("irc", None), Ok(()));
+    assert_eq!(
+        connection.validate("irc", Some(auth::SaslMechanism::External)),
+        Ok(())
+    );
+    assert!(ConnectionConfig::default()
+        .validate("irc", Some(auth::SaslMechanism::External))
+        .is_err());
+    let connection = ConnectionConfig {
+        use_tls: false,
+        ..connection
+    };
+    assert!(connection.validate("irc", None).is_err());
+}
+
+#[test]
+fn test_default_feeds() {
+    let server =
+        r##"{"nickname": "bot", "host": "irc", "channels": [], "factorio_channel": "#f"}"##;
+    let mut config: ConfigServer = serde_json::from_str(server).unwrap();
+    config.fill_defaults();
+    assert!(config.uses_default_feeds);
+    let names = config.feeds().iter().map(|feed| feed.name.as_str());
+    assert_eq!(
+        names.collect::<Vec<_>>(),
+        [
+            "factorio_stable",
+            "factorio_experimental",
+            "friday_facts",
+            "alt_f4"
+        ]
+    );
+    assert_eq!(
+        config.topic_segments["#f"],
+        ["welcome", "stable", "experimental", "fff"]
+    );
+
+    let mut config: ConfigServer =
+        serde_json::from_str(&server.replace("}", r#", "feeds": []}"#)).unwrap();
+    config.fill_defaults();
+    assert!(!config.uses_default_feeds);
+    assert!(config.feeds().is_empty());
+    assert!(config.topic_segments.is_empty());
+}
+
+#[test]
+fn test_action_enabled() {
+    let server = r##"{
+        "nickname": "bot",
+        "host": "irc",
+        "channels": [],
+        "actions": {"disabled": ["wiki"]},
+        "channel_actions": {"#Factorio": {"enabled": ["help"]}}
+    }"##;
+    let config: ConfigServer = serde_json::from_str(server).unwrap();
+    assert!(config.action_enabled("help", None));
+    assert!(!config.action_enabled("wiki", None));
+    assert!(config.action_enabled("help", Some("#factorio")));
+    assert!(!config.action_enabled("topic", Some("#FACTORIO")));
+    assert!(config.action_enabled("topic", Some("#other")));
 }
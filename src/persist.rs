@@ -0,0 +1,40 @@
+//! Persisted state
+//!
+//! Everything the bot remembers between restarts is stored as json in the `persist/` folder.
+
+use crate::error::Error;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    fs,
+    io::{self, Write as _},
+};
+
+/// Write `value` to `file` as json.
+///
+/// The json is written to a temporary file first, which is then moved over `file`. This way the
+/// file is never left half-written if the bot crashes or the disk runs full.
+pub fn save<T: Serialize + ?Sized>(file: &str, value: &T) -> Result<(), Error> {
+    let json = serde_json::to_string_pretty(value)
+        .map_err(|e| Error::Persist(format!("Could not serialize {}: {:?}", file, e)))?;
+
+    let temp_file = format!("{}.tmp", file);
+    let mut f = fs::File::create(&temp_file).map_err(|e| {
+        Error::Persist(format!("Could not open {} for writing: {:?}", temp_file, e))
+    })?;
+    f.write_all(json.as_bytes())
+        .and_then(|_| f.sync_all())
+        .map_err(|e| Error::Persist(format!("Could not write {}: {:?}", temp_file, e)))?;
+
+    fs::rename(&temp_file, file)
+        .map_err(|e| Error::Persist(format!("Could not move {} to {}: {:?}", temp_file, file, e)))
+}
+
+/// Load `file` as json. Returns the default value if the file does not exist yet.
+pub fn load<T: DeserializeOwned + Default>(file: &str) -> Result<T, Error> {
+    match fs::File::open(file) {
+        Ok(f) => serde_json::from_reader(f)
+            .map_err(|e| Error::Persist(format!("Could not parse {}: {:?}", file, e))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(T::default()),
+        Err(e) => Err(Error::Persist(format!("Could not open {}: {:?}", file, e))),
+    }
+}
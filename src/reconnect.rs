@@ -0,0 +1,109 @@
+//! Reconnecting
+//!
+//! When the connection to a server is lost, the bot waits before reconnecting. The delay doubles
+//! after every failed attempt, up to a maximum, and a random part is added so that bots on the
+//! same network don't all reconnect at the same moment. Once a connection has been up for a while
+//! the delay starts over.
+//!
+//! Every failed attempt moves on to the next host of the server, so if the main host is down the
+//! fallback hosts are tried in turn. After a stable connection, the main host is tried first again.
+
+use rand::Rng;
+use std::time::Duration;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReconnectConfig {
+    /// The delay after the first failed connection
+    pub initial_delay_seconds: u64,
+    pub max_delay_seconds: u64,
+    /// A connection that has been up for this long is considered stable, after which the delay
+    /// starts over from `initial_delay_seconds`
+    pub stable_after_seconds: u64,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            initial_delay_seconds: 5,
+            max_delay_seconds: 60 * 10,
+            stable_after_seconds: 60 * 5,
+        }
+    }
+}
+
+pub struct Backoff {
+    config: ReconnectConfig,
+    /// The amount of attempts since the last stable connection
+    attempt: u32,
+    host_index: usize,
+}
+
+impl Backoff {
+    pub fn new(config: ReconnectConfig) -> Self {
+        Self {
+            config,
+            attempt: 0,
+            host_index: 0,
+        }
+    }
+
+    /// The host to connect to next
+    pub fn host<'a>(&self, hosts: &'a [String]) -> &'a str {
+        &hosts[self.host_index % hosts.len()]
+    }
+
+    /// How long to wait before reconnecting, after a connection that was up for `connected_for`
+    pub fn next_delay(&mut self, connected_for: Duration) -> Duration {
+        if connected_for >= Duration::from_secs(self.config.stable_after_seconds) {
+            self.attempt = 0;
+            self.host_index = 0;
+        } else {
+            self.host_index = self.host_index.wrapping_add(1);
+        }
+        let delay = self.delay_without_jitter();
+        self.attempt = self.attempt.saturating_add(1);
+        // Wait between half and all of the delay
+        let half = delay / 2;
+        half + rand::thread_rng().gen_range(Duration::ZERO..=delay - half)
+    }
+
+    fn delay_without_jitter(&self) -> Duration {
+        let max = self.config.max_delay_seconds;
+        let delay = 2u64
+            .checked_pow(self.attempt)
+            .and_then(|factor| self.config.initial_delay_seconds.checked_mul(factor))
+            .map_or(max, |delay| delay.min(max));
+        Duration::from_secs(delay)
+    }
+}
+
+#[test]
+fn test_backoff() {
+    let mut backoff = Backoff::new(ReconnectConfig {
+        initial_delay_seconds: 5,
+        max_delay_seconds: 30,
+        stable_after_seconds: 60,
+    });
+    let hosts = vec![String::from("main"), String::from("fallback")];
+    let short = Duration::from_secs(1);
+
+    assert_eq!(backoff.host(&hosts), "main");
+    let expected = [5, 10, 20, 30, 30];
+    for seconds in expected.iter() {
+        let delay = backoff.next_delay(short);
+        let seconds = Duration::from_secs(*seconds);
+        assert!(delay >= seconds / 2 && delay <= seconds, "{:?}", delay);
+    }
+    assert_eq!(backoff.host(&hosts), "fallback");
+
+    backoff.attempt = 100;
+    assert_eq!(backoff.delay_without_jitter(), Duration::from_secs(30));
+
+    // A stable connection starts over
+    let delay = backoff.next_delay(Duration::from_secs(60));
+    assert!(delay <= Duration::from_secs(5));
+    assert_eq!(backoff.host(&hosts), "main");
+    backoff.next_delay(short);
+    assert_eq!(backoff.host(&hosts), "fallback");
+}